@@ -0,0 +1,62 @@
+// Times the edits rim makes on every keystroke against a 10 MB buffer.
+// Run with `cargo bench`; exits non-zero if any edit takes 1 ms or more.
+use std::hint::black_box;
+use std::process;
+use std::time::{Duration, Instant};
+
+#[allow(dead_code)]
+#[path = "../src/rope.rs"]
+mod rope;
+
+use rope::Rope;
+
+const FILE_SIZE: usize = 10 * 1024 * 1024;
+const ITERATIONS: usize = 1000;
+const BUDGET: Duration = Duration::from_millis(1);
+
+fn time(name: &str, rope: &mut Rope, mut edit: impl FnMut(&mut Rope, usize)) -> bool {
+    let start = Instant::now();
+    for i in 0..ITERATIONS {
+        edit(rope, i);
+    }
+    let each = start.elapsed() / ITERATIONS as u32;
+    println!("{:<24} {:>10.3?}", name, each);
+    each < BUDGET
+}
+
+fn main() {
+    let line = "The quick brown fox jumps over the lazy dog, héllo 日本語. ".repeat(2);
+    let lines: Vec<String> = (0..FILE_SIZE / line.len()).map(|i| format!("{:>8} {}", i, line)).collect();
+    let mut rope = Rope::from(lines);
+    let middle = rope.len() / 2;
+    println!("{} lines, {} bytes", rope.len(), rope.join("\n").len());
+
+    let results = [
+        time("insert char", &mut rope, |rope, i| rope.insert_char((middle, i % 50), 'x')),
+        time("remove char", &mut rope, |rope, i| {
+            rope.remove((middle, i % 50), (middle, i % 50 + 1));
+        }),
+        time("insert line", &mut rope, |rope, i| rope.insert_line(middle + i, "new line")),
+        time("remove line", &mut rope, |rope, _| {
+            rope.remove_line(middle);
+        }),
+        time("join lines", &mut rope, |rope, _| {
+            let len = rope.line_len(middle);
+            rope.remove((middle, len), (middle + 1, 0));
+            rope.insert((middle, len), "\n");
+        }),
+        time("line lookup", &mut rope, |rope, i| {
+            black_box(rope.line(i * 97 % rope.len()));
+        }),
+        time("undo snapshot", &mut rope, |rope, _| {
+            let snapshot = rope.clone();
+            rope.insert_char((middle, 0), 'x');
+            black_box(snapshot);
+            rope.remove((middle, 0), (middle, 1));
+        }),
+    ];
+    if results.contains(&false) {
+        eprintln!("an edit took {:?} or more", BUDGET);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,37 @@
+// The system clipboard is only available with the `clipboard` feature; without
+// it, or when no clipboard can be reached (headless, SSH), every call is a no-op.
+#[cfg(feature = "clipboard")]
+pub struct Clipboard(Option<arboard::Clipboard>);
+
+#[cfg(feature = "clipboard")]
+impl Clipboard {
+    pub fn new() -> Self {
+        Clipboard(arboard::Clipboard::new().ok())
+    }
+
+    pub fn set(&mut self, text: &str) {
+        if let Some(clipboard) = &mut self.0 {
+            let _ = clipboard.set_text(text);
+        }
+    }
+
+    pub fn get(&mut self) -> Option<String> {
+        self.0.as_mut()?.get_text().ok()
+    }
+}
+
+#[cfg(not(feature = "clipboard"))]
+pub struct Clipboard;
+
+#[cfg(not(feature = "clipboard"))]
+impl Clipboard {
+    pub fn new() -> Self {
+        Clipboard
+    }
+
+    pub fn set(&mut self, _text: &str) {}
+
+    pub fn get(&mut self) -> Option<String> {
+        None
+    }
+}
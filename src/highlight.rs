@@ -0,0 +1,135 @@
+use std::path::Path;
+use termion::color::AnsiValue;
+
+const KEYWORD: AnsiValue = AnsiValue(5);
+const STRING: AnsiValue = AnsiValue(2);
+const COMMENT: AnsiValue = AnsiValue(8);
+const NUMBER: AnsiValue = AnsiValue(6);
+
+pub type Span = (usize, usize, AnsiValue);
+
+pub trait Highlighter {
+    // Spans are character columns, end exclusive.
+    fn highlight_line(&self, line: &str) -> Vec<Span>;
+}
+
+pub fn for_path(path: &str) -> &'static dyn Highlighter {
+    match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+        Some("rs") => &RustHighlighter,
+        Some("py") => &PythonHighlighter,
+        Some("c" | "h" | "cpp" | "hpp") => &CHighlighter,
+        _ => &DefaultHighlighter,
+    }
+}
+
+pub struct RustHighlighter;
+pub struct PythonHighlighter;
+pub struct CHighlighter;
+pub struct DefaultHighlighter;
+
+impl Highlighter for RustHighlighter {
+    fn highlight_line(&self, line: &str) -> Vec<Span> {
+        RUST.highlight(line)
+    }
+}
+
+impl Highlighter for PythonHighlighter {
+    fn highlight_line(&self, line: &str) -> Vec<Span> {
+        PYTHON.highlight(line)
+    }
+}
+
+impl Highlighter for CHighlighter {
+    fn highlight_line(&self, line: &str) -> Vec<Span> {
+        C.highlight(line)
+    }
+}
+
+impl Highlighter for DefaultHighlighter {
+    fn highlight_line(&self, _line: &str) -> Vec<Span> {
+        Vec::new()
+    }
+}
+
+struct Rules {
+    keywords: &'static [&'static str],
+    line_comment: &'static str,
+    quotes: &'static [char],
+}
+
+const RUST: Rules = Rules {
+    keywords: &[
+        "as", "break", "const", "continue", "crate", "else", "enum", "false", "fn", "for", "if", "impl", "in",
+        "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static",
+        "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while",
+    ],
+    line_comment: "//",
+    quotes: &['"'],
+};
+
+const PYTHON: Rules = Rules {
+    keywords: &[
+        "and", "as", "assert", "break", "class", "continue", "def", "del", "elif", "else", "except", "False",
+        "finally", "for", "from", "if", "import", "in", "is", "lambda", "None", "not", "or", "pass", "raise",
+        "return", "True", "try", "while", "with", "yield",
+    ],
+    line_comment: "#",
+    quotes: &['"', '\''],
+};
+
+const C: Rules = Rules {
+    keywords: &[
+        "break", "case", "char", "const", "continue", "default", "do", "double", "else", "enum", "extern",
+        "float", "for", "if", "int", "long", "return", "short", "signed", "sizeof", "static", "struct",
+        "switch", "typedef", "union", "unsigned", "void", "while",
+    ],
+    line_comment: "//",
+    quotes: &['"', '\''],
+};
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+impl Rules {
+    fn highlight(&self, line: &str) -> Vec<Span> {
+        let chars: Vec<char> = line.chars().collect();
+        let comment: Vec<char> = self.line_comment.chars().collect();
+        let mut spans = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let start = i;
+            let color = if chars[i..].starts_with(&comment) {
+                i = chars.len();
+                Some(COMMENT)
+            } else if self.quotes.contains(&c) {
+                i += 1;
+                while i < chars.len() && chars[i] != c {
+                    i += if chars[i] == '\\' { 2 } else { 1 };
+                }
+                i = (i + 1).min(chars.len());
+                Some(STRING)
+            } else if is_word_char(c) {
+                while i < chars.len() && is_word_char(chars[i]) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                if c.is_ascii_digit() {
+                    Some(NUMBER)
+                } else if self.keywords.contains(&word.as_str()) {
+                    Some(KEYWORD)
+                } else {
+                    None
+                }
+            } else {
+                i += 1;
+                None
+            };
+            if let Some(color) = color {
+                spans.push((start, i, color));
+            }
+        }
+        spans
+    }
+}
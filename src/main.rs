@@ -1,276 +1,3608 @@
-use std::fs::{File, OpenOptions};
-use std::io::{self, BufRead, BufReader, Write};
+mod clipboard;
+mod highlight;
+mod rope;
+
+use clipboard::Clipboard;
+use highlight::Highlighter;
+use rope::Rope;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::ops::Range;
 use std::path::Path;
+use std::process::{self, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::time::Duration;
-use termion::event::Key;
-use termion::input::TermRead;
+use termion::event::{Event, Key, MouseButton, MouseEvent};
+use termion::input::{MouseTerminal, TermRead};
 use termion::raw::IntoRawMode;
-use termion::screen::AlternateScreen;
 use termion::color;
 use termion::screen::IntoAlternateScreen;
 
 
-enum Mode {
-    Normal,
-    Insert,
-    Command,
+const UNDO_DEPTH: usize = 100;
+const TAB_WIDTH: usize = 8;
+const JUMP_LIST_DEPTH: usize = 100;
+const RESIZE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const WHEEL_SCROLL_LINES: usize = 3;
+// Counts are capped so that `row + count` and friends cannot overflow.
+const MAX_COUNT: usize = 999_999;
+
+fn byte_index(line: &str, col: usize) -> usize {
+    line.char_indices().nth(col).map_or(line.len(), |(i, _)| i)
 }
 
-struct Editor {
-    lines: Vec<String>,
-    cursor: (usize, usize),
-    mode: Mode,
-    file_path: String,
-    status_message: String,
-    scroll_offset: usize,
+fn char_len(line: &str) -> usize {
+    line.chars().count()
 }
 
-impl Editor {
-    fn new(file_path: &str) -> io::Result<Self> {
-        let path = Path::new(file_path);
-        let lines = if path.exists() {
-            let file = File::open(path)?;
-            BufReader::new(file).lines().collect::<io::Result<Vec<String>>>()?
-        } else {
-            vec![String::new()]
-        };
+fn leading_whitespace(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
 
-        Ok(Editor {
-            lines,
-            cursor: (0, 0),
-            mode: Mode::Normal,
-            file_path: file_path.to_string(),
-            status_message: String::new(),
-            scroll_offset: 0,
+fn first_non_blank(line: &str) -> usize {
+    line.chars().take_while(|c| c.is_whitespace()).count()
+}
+
+fn split_lines(content: &str) -> (Vec<String>, LineEnding) {
+    let crlf = content.matches("\r\n").count();
+    let lf = content.matches('\n').count() - crlf;
+    let line_ending = if crlf > lf { LineEnding::CrLf } else { LineEnding::Lf };
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    if lines.is_empty() {
+        lines.push(String::new());
+    }
+    (lines, line_ending)
+}
+
+fn toggle_case(c: char) -> char {
+    let toggled = if c.is_lowercase() { c.to_uppercase().next() } else { c.to_lowercase().next() };
+    toggled.unwrap_or(c)
+}
+
+fn split_number(text: &str) -> Option<(usize, &str)> {
+    let digits = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let number = text[..digits].parse().ok()?;
+    Some((number, &text[digits..]))
+}
+
+fn split_substitute(args: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = args.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'/') => {
+                parts.last_mut().unwrap().push('/');
+                chars.next();
+            }
+            '/' => parts.push(String::new()),
+            _ => parts.last_mut().unwrap().push(c),
+        }
+    }
+    parts
+}
+
+// File names starting with `partial`, each with the directory part of
+// `partial` kept in front and a trailing `/` on directories.
+fn complete_path(partial: &str) -> Vec<String> {
+    let (dir, prefix) = match partial.rfind('/') {
+        Some(slash) => partial.split_at(slash + 1),
+        None => ("", partial),
+    };
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, name, slash))
         })
+        .collect();
+    names.sort();
+    names
+}
+
+fn char_class(c: char) -> u8 {
+    if c.is_whitespace() {
+        0
+    } else if c.is_alphanumeric() || c == '_' {
+        1
+    } else {
+        2
     }
+}
 
-    fn run(&mut self) -> io::Result<()> {
-        let stdout = io::stdout().into_raw_mode()?;
-        let mut screen = stdout.into_alternate_screen()?;
-        let mut stdin = termion::async_stdin().keys();
+fn char_width(c: char, vcol: usize, tab_width: usize) -> usize {
+    if c == '\t' {
+        tab_width - vcol % tab_width
+    } else {
+        1
+    }
+}
 
-        self.display(&mut screen)?;
+fn visual_col(line: &str, col: usize, tab_width: usize) -> usize {
+    line.chars()
+        .take(col)
+        .fold(0, |vcol, c| vcol + char_width(c, vcol, tab_width))
+}
 
-        loop {
-            if let Some(Ok(key)) = stdin.next() {
-                if self.handle_key(key)? {
-                    break;
+fn col_at_visual(line: &str, target: usize, tab_width: usize) -> usize {
+    let mut vcol = 0;
+    for (col, c) in line.chars().enumerate() {
+        vcol += char_width(c, vcol, tab_width);
+        if vcol > target {
+            return col;
+        }
+    }
+    char_len(line)
+}
+
+fn line_number_width(lines: &Rope) -> usize {
+    lines.len().to_string().len().max(4)
+}
+
+// Keys that start a change, refused while the buffer is readonly. Keys
+// after a pending prefix (`fx`, `ma`, ...) are not checked.
+fn is_edit_key(mode: &Mode, key: Key) -> bool {
+    match mode {
+        Mode::Normal => matches!(
+            key,
+            Key::Char('i' | 'a' | 'A' | 'I' | 'o' | 'O' | 's' | 'S' | 'x' | 'r' | 'J' | '~' | 'p' | 'P' | '.')
+                | Key::Char('c' | 'C' | 'd' | 'D' | '>' | '<' | 'u')
+                | Key::Ctrl('a' | 'x' | 'r')
+        ),
+        Mode::Visual => matches!(key, Key::Char('d' | '~')),
+        Mode::VisualLine => matches!(key, Key::Char('d' | '>' | '<')),
+        _ => false,
+    }
+}
+
+// Pending-key marker for Ctrl-w window commands.
+const CTRL_W: char = '\u{17}';
+
+const BRACKET_PAIRS: [(char, char); 4] = [('(', ')'), ('[', ']'), ('{', '}'), ('<', '>')];
+// `<` and `>` are comparisons as often as brackets, so only `%` pairs them.
+const HIGHLIGHT_PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+// Looks for the bracket matching the one at (row, col) among `pairs`,
+// searching only the lines in `rows`.
+fn find_matching_bracket(
+    lines: &Rope,
+    row: usize,
+    col: usize,
+    pairs: &[(char, char)],
+    rows: Range<usize>,
+) -> Option<(usize, usize)> {
+    let c = lines.line(row).chars().nth(col)?;
+    let (open, close) = *pairs.iter().find(|&&(open, close)| c == open || c == close)?;
+    let mut depth = 0;
+    if c == open {
+        for r in row..rows.end.min(lines.len()) {
+            let skip = if r == row { col } else { 0 };
+            for (i, ch) in lines.line(r).chars().enumerate().skip(skip) {
+                if ch == open {
+                    depth += 1;
+                } else if ch == close {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some((r, i));
+                    }
                 }
-                self.display(&mut screen)?;
             }
-            std::thread::sleep(Duration::from_millis(10));
         }
+    } else {
+        for r in (rows.start..=row).rev() {
+            let chars: Vec<char> = lines.line(r).chars().collect();
+            let end = if r == row { col + 1 } else { chars.len() };
+            for i in (0..end).rev() {
+                if chars[i] == close {
+                    depth += 1;
+                } else if chars[i] == open {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some((r, i));
+                    }
+                }
+            }
+        }
+    }
+    None
+}
 
-        write!(screen, "{}", termion::cursor::Show)?;
-        Ok(())
+type LineRange = (usize, usize);
+
+enum Mode {
+    Normal,
+    Insert,
+    Command,
+    Visual,
+    VisualLine,
+    Search(SearchDirection),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SearchDirection {
+    Forward,
+    Backward,
+}
+
+impl SearchDirection {
+    fn reversed(self) -> Self {
+        match self {
+            SearchDirection::Forward => SearchDirection::Backward,
+            SearchDirection::Backward => SearchDirection::Forward,
+        }
     }
+}
 
-    fn display(&self, screen: &mut AlternateScreen<termion::raw::RawTerminal<io::Stdout>>) -> io::Result<()> {
-        write!(screen, "{}{}", termion::clear::All, termion::cursor::Goto(1, 1))?;
+#[derive(Clone, Copy, PartialEq)]
+enum Highlight {
+    Selection,
+    SearchMatch,
+    MatchingBracket,
+}
 
-        let (width, height) = termion::terminal_size()?;
-        let visible_lines = (height - 2) as usize;
-        let line_number_width = 4;
-        let content_width = width as usize - line_number_width - 3; // 3 for the separator and padding
+impl Highlight {
+    fn background(self) -> String {
+        match self {
+            Highlight::Selection => color::Bg(color::LightBlack).to_string(),
+            Highlight::SearchMatch => color::Bg(color::Yellow).to_string(),
+            Highlight::MatchingBracket => color::Bg(color::Cyan).to_string(),
+        }
+    }
+}
 
-        for (i, line) in self.lines.iter().enumerate().skip(self.scroll_offset).take(visible_lines) {
-            // Line number
-            write!(screen, "{}", termion::cursor::Goto(1, (i - self.scroll_offset + 1) as u16))?;
-            write!(screen, "{}{:>4} │ ", color::Fg(color::LightBlue), i + 1)?;
+#[derive(Clone, Copy, PartialEq)]
+enum LineEnding {
+    Lf,
+    CrLf,
+}
 
-            // Line content
-            write!(screen, "{}", color::Fg(color::Reset))?;
-            if line.len() > content_width {
-                writeln!(screen, "{}...", &line[..content_width - 3])?;
-            } else {
-                writeln!(screen, "{}", line)?;
-            }
+impl LineEnding {
+    fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
         }
+    }
+}
 
-        self.draw_status_bar(screen)?;
+#[derive(Clone, Copy)]
+enum YankKind {
+    Char,
+    Line,
+}
 
-        // Update cursor position
-        let cursor_y = (self.cursor.0 - self.scroll_offset + 1) as u16;
-        let cursor_x = (self.cursor.1 + line_number_width + 3) as u16;
-        write!(screen, "{}{}", termion::cursor::Goto(cursor_x, cursor_y), termion::cursor::Show)?;
+struct Snapshot {
+    lines: Rope,
+    cursor: (usize, usize),
+}
 
-        screen.flush()?;
-        Ok(())
+struct UndoHistory {
+    undo_stack: VecDeque<Snapshot>,
+    redo_stack: Vec<Snapshot>,
+    max_depth: usize,
+    grouping: bool,
+}
+
+impl UndoHistory {
+    fn new(max_depth: usize) -> Self {
+        UndoHistory {
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            max_depth,
+            grouping: false,
+        }
     }
 
-    fn draw_status_bar(&self, screen: &mut AlternateScreen<termion::raw::RawTerminal<io::Stdout>>) -> io::Result<()> {
-        let (width, height) = termion::terminal_size()?;
-        write!(
-            screen,
-            "{}{}{}",
-            termion::cursor::Goto(1, height - 1),
-            color::Bg(color::Blue),
-            " ".repeat(width as usize)
-        )?;
+    fn record(&mut self, lines: &Rope, cursor: (usize, usize)) {
+        self.undo_stack.push_back(Snapshot { lines: lines.clone(), cursor });
+        while self.undo_stack.len() > self.max_depth {
+            self.undo_stack.pop_front();
+        }
+        self.redo_stack.clear();
+        self.grouping = false;
+    }
 
-        write!(
-            screen,
-            "{}{}-- {} -- {}:{} --{}{}{}",
-            termion::cursor::Goto(1, height),
-            color::Fg(color::White),
-            match self.mode {
-                Mode::Normal => "NORMAL",
-                Mode::Insert => "INSERT",
-                Mode::Command => "COMMAND",
-            },
-            self.cursor.0 + 1,
-            self.cursor.1 + 1,
-            self.status_message,
-            color::Fg(color::Reset),
-            color::Bg(color::Reset)
-        )?;
-        Ok(())
+    fn record_grouped(&mut self, lines: &Rope, cursor: (usize, usize)) {
+        if !self.grouping {
+            self.record(lines, cursor);
+            self.grouping = true;
+        }
     }
 
-    fn handle_key(&mut self, key: Key) -> io::Result<bool> {
-        match self.mode {
-            Mode::Normal => match key {
-                Key::Char('q') => return Ok(true),
-                Key::Char('i') => self.mode = Mode::Insert,
-                Key::Char(':') => {
-                    self.mode = Mode::Command;
-                    self.status_message.clear();
-                },
-                Key::Up => self.move_cursor_up(),
-                Key::Down => self.move_cursor_down(),
-                Key::Left => self.move_cursor_left(),
-                Key::Right => self.move_cursor_right(),
-                _ => {}
-            },
-            Mode::Insert => match key {
-                Key::Esc => self.mode = Mode::Normal,
-                Key::Char('\n') => self.insert_newline(),
-                Key::Char(c) => self.insert_char(c),
-                Key::Backspace => self.delete_char(),
-                Key::Up => self.move_cursor_up(),
-                Key::Down => self.move_cursor_down(),
-                Key::Left => self.move_cursor_left(),
-                Key::Right => self.move_cursor_right(),
-                _ => {}
-            },
-            Mode::Command => match key {
-                Key::Char('\n') => return self.execute_command(),
-                Key::Esc => {
-                    self.mode = Mode::Normal;
-                    self.status_message.clear();
+    fn break_group(&mut self) {
+        self.grouping = false;
+    }
+
+    fn undo(&mut self, lines: &Rope, cursor: (usize, usize)) -> Option<Snapshot> {
+        let snapshot = self.undo_stack.pop_back()?;
+        self.redo_stack.push(Snapshot { lines: lines.clone(), cursor });
+        Some(snapshot)
+    }
+
+    fn redo(&mut self, lines: &Rope, cursor: (usize, usize)) -> Option<Snapshot> {
+        let snapshot = self.redo_stack.pop()?;
+        self.undo_stack.push_back(Snapshot { lines: lines.clone(), cursor });
+        Some(snapshot)
+    }
+}
+
+struct ChangeRecorder {
+    keys: Vec<Key>,
+    changed: bool,
+    discard: bool,
+    replaying: bool,
+}
+
+impl ChangeRecorder {
+    fn new() -> Self {
+        ChangeRecorder {
+            keys: Vec::new(),
+            changed: false,
+            discard: false,
+            replaying: false,
+        }
+    }
+
+    fn start(&mut self) {
+        self.keys.clear();
+        self.changed = false;
+        self.discard = false;
+    }
+
+    fn finish(&mut self) -> Option<Vec<Key>> {
+        let keys = std::mem::take(&mut self.keys);
+        let completed = self.changed && !self.discard;
+        self.changed = false;
+        completed.then_some(keys)
+    }
+}
+
+struct Completion {
+    original: String,
+    candidates: Vec<String>,
+    index: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Config {
+    tab_width: usize,
+    expand_tab: bool,
+    auto_indent: bool,
+    show_line_numbers: bool,
+    relative_line_numbers: bool,
+    scroll_margin: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            tab_width: TAB_WIDTH,
+            expand_tab: false,
+            auto_indent: true,
+            show_line_numbers: true,
+            relative_line_numbers: false,
+            scroll_margin: 0,
+        }
+    }
+}
+
+impl Config {
+    fn load() -> (Self, Vec<String>) {
+        let mut config = Config::default();
+        let Some(home) = std::env::var_os("HOME") else {
+            return (config, Vec::new());
+        };
+        let path = Path::new(&home).join(".config/rim/config.toml");
+        let Ok(content) = fs::read_to_string(&path) else {
+            return (config, Vec::new());
+        };
+        let table = match content.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(err) => return (config, vec![format!("config.toml: {}", err.message())]),
+        };
+
+        let mut warnings = Vec::new();
+        for (key, value) in &table {
+            let applied = match key.as_str() {
+                "tab_width" => value.as_integer().filter(|&n| n > 0).map(|n| config.tab_width = n as usize),
+                "expand_tab" => value.as_bool().map(|b| config.expand_tab = b),
+                "auto_indent" => value.as_bool().map(|b| config.auto_indent = b),
+                "show_line_numbers" => value.as_bool().map(|b| config.show_line_numbers = b),
+                "relative_line_numbers" => value.as_bool().map(|b| config.relative_line_numbers = b),
+                "scroll_margin" => value.as_integer().filter(|&n| n >= 0).map(|n| config.scroll_margin = n as usize),
+                _ => {
+                    warnings.push(format!("config.toml: unknown key {}", key));
+                    continue;
                 }
-                Key::Char(c) => self.status_message.push(c),
-                Key::Backspace => { self.status_message.pop(); }
-                _ => {}
-            },
+            };
+            if applied.is_none() {
+                warnings.push(format!("config.toml: invalid value for {}", key));
+            }
         }
-        Ok(false)
+        (config, warnings)
     }
 
-    fn move_cursor_up(&mut self) {
-        if self.cursor.0 > 0 {
-            self.cursor.0 -= 1;
-            self.cursor.1 = self.cursor.1.min(self.lines[self.cursor.0].len());
-            if self.cursor.0 < self.scroll_offset {
-                self.scroll_offset = self.cursor.0;
+    // Applies `~/.rimrc` on top of these settings, returning a warning if it
+    // could not be used, in which case none of it is applied. A missing file
+    // is not an error.
+    fn load_rimrc(&mut self, path: &Path) -> Option<String> {
+        let result = match fs::read_to_string(path) {
+            Ok(content) => self.parse_rimrc(&content),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return None,
+            Err(err) => Err(err.to_string()),
+        };
+        match result {
+            Ok(config) => {
+                *self = config;
+                None
             }
+            Err(err) => Some(format!(".rimrc: {}; none of it was applied", err)),
         }
     }
 
-    fn move_cursor_down(&mut self) {
-        if self.cursor.0 < self.lines.len() - 1 {
-            self.cursor.0 += 1;
-            self.cursor.1 = self.cursor.1.min(self.lines[self.cursor.0].len());
-            let (_, height) = termion::terminal_size().unwrap();
-            if self.cursor.0 >= self.scroll_offset + height as usize - 3 {
-                self.scroll_offset = self.cursor.0.saturating_sub(height as usize - 3);
+    // `~/.rimrc` holds `key = value` lines, with `#` starting a comment.
+    fn parse_rimrc(&self, content: &str) -> Result<Self, String> {
+        let mut config = self.clone();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("line {}: expected key = value", i + 1));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            let applied = match key {
+                "tab_width" => value.parse().ok().filter(|&n| n > 0).map(|n| config.tab_width = n),
+                "expandtab" => value.parse().ok().map(|b| config.expand_tab = b),
+                "number" => value.parse().ok().map(|b| config.show_line_numbers = b),
+                "relativenumber" => value.parse().ok().map(|b| config.relative_line_numbers = b),
+                _ => return Err(format!("line {}: unknown key {}", i + 1, key)),
+            };
+            if applied.is_none() {
+                return Err(format!("line {}: invalid value for {}", i + 1, key));
             }
         }
+        Ok(config)
     }
+}
 
-    fn move_cursor_left(&mut self) {
-        if self.cursor.1 > 0 {
-            self.cursor.1 -= 1;
-        } else if self.cursor.0 > 0 {
-            self.cursor.0 -= 1;
-            self.cursor.1 = self.lines[self.cursor.0].len();
+struct Buffer {
+    lines: Rope,
+    cursor: (usize, usize),
+    file_path: String,
+    scroll_offset: usize,
+    h_scroll_offset: usize,
+    modified: bool,
+    undo_history: UndoHistory,
+    marks: HashMap<char, (usize, usize)>,
+    jump_list: Vec<(usize, usize)>,
+    jump_pos: usize,
+    line_ending: LineEnding,
+    trailing_newline: bool,
+    readonly: bool,
+    highlighter: &'static dyn Highlighter,
+}
+
+impl Buffer {
+    fn open(file_path: &str) -> io::Result<Self> {
+        let path = Path::new(file_path);
+        if path.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Is a directory"));
+        }
+        if !path.exists() {
+            // A new file gets a final newline when it is first saved.
+            return Ok(Buffer::from_content(file_path, "\n"));
         }
+        let content = match String::from_utf8(fs::read(path)?) {
+            Ok(content) if !content.contains('\0') => content,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Binary file, not opened")),
+        };
+        let mut buffer = Buffer::from_content(file_path, &content);
+        buffer.readonly = fs::metadata(path).is_ok_and(|metadata| metadata.permissions().readonly());
+        Ok(buffer)
     }
 
-    fn move_cursor_right(&mut self) {
-        if self.cursor.1 < self.lines[self.cursor.0].len() {
-            self.cursor.1 += 1;
-        } else if self.cursor.0 < self.lines.len() - 1 {
-            self.cursor.0 += 1;
-            self.cursor.1 = 0;
+    fn from_content(file_path: &str, content: &str) -> Self {
+        let (lines, line_ending) = split_lines(content);
+        Buffer {
+            lines: Rope::from(lines),
+            cursor: (0, 0),
+            file_path: file_path.to_string(),
+            scroll_offset: 0,
+            h_scroll_offset: 0,
+            modified: false,
+            undo_history: UndoHistory::new(UNDO_DEPTH),
+            marks: HashMap::new(),
+            jump_list: Vec::new(),
+            jump_pos: 0,
+            line_ending,
+            trailing_newline: content.ends_with('\n'),
+            readonly: false,
+            highlighter: highlight::for_path(file_path),
         }
     }
+}
 
-    fn execute_command(&mut self) -> io::Result<bool> {
-        match self.status_message.as_str() {
-            "w" => self.save()?,
-            "q" => return Ok(true),
-            "wq" => {
-                self.save()?;
-                return Ok(true);
-            }
-            _ => self.status_message = "Invalid command".to_string(),
+// The cursor and scroll position of a window onto one of the buffers.
+#[derive(Clone, Copy)]
+struct Pane {
+    buffer: usize,
+    cursor: (usize, usize),
+    scroll_offset: usize,
+    h_scroll_offset: usize,
+}
+
+// A rectangle of screen cells, 0-based. `rows` excludes the pane's status line.
+#[derive(Clone, Copy)]
+struct Area {
+    top: usize,
+    left: usize,
+    rows: usize,
+    cols: usize,
+}
+
+struct View<'a> {
+    lines: &'a Rope,
+    file_path: &'a str,
+    modified: bool,
+    readonly: bool,
+    highlighter: &'static dyn Highlighter,
+    pane: Pane,
+    active: bool,
+}
+
+struct Editor {
+    mode: Mode,
+    status_message: String,
+    overlay: Vec<String>,
+    visual_start: Option<(usize, usize)>,
+    tab_width: usize,
+    expand_tab: bool,
+    auto_indent: bool,
+    trim_trailing: bool,
+    yank_register: Option<(String, YankKind)>,
+    pending_key: Option<char>,
+    pending_count: Option<usize>,
+    last_search: Option<String>,
+    command_history: Vec<String>,
+    history_pos: Option<usize>,
+    completion: Option<Completion>,
+    last_find: Option<(char, char)>,
+    search_direction: SearchDirection,
+    recorder: ChangeRecorder,
+    last_change: Option<Vec<Key>>,
+    relative_line_numbers: bool,
+    show_line_numbers: bool,
+    scroll_margin: usize,
+    syntax_highlighting: bool,
+    buffers: Vec<Buffer>,
+    current_buffer: usize,
+    split: Option<Pane>,
+    split_focus_first: bool,
+    split_vertical: bool,
+    macros: HashMap<char, Vec<Key>>,
+    recording_macro: Option<char>,
+    last_macro: Option<char>,
+    playing_macro: bool,
+    failed: bool,
+    clipboard: Clipboard,
+    size: (u16, u16),
+}
+
+impl Editor {
+    fn new(file_path: &str) -> io::Result<Self> {
+        Ok(Editor::with_buffer(Buffer::open(file_path)?))
+    }
+
+    fn with_buffer(buffer: Buffer) -> Self {
+        Editor {
+            mode: Mode::Normal,
+            status_message: String::new(),
+            overlay: Vec::new(),
+            visual_start: None,
+            tab_width: TAB_WIDTH,
+            expand_tab: false,
+            auto_indent: true,
+            trim_trailing: false,
+            yank_register: None,
+            pending_key: None,
+            pending_count: None,
+            last_search: None,
+            command_history: Vec::new(),
+            history_pos: None,
+            completion: None,
+            last_find: None,
+            search_direction: SearchDirection::Forward,
+            recorder: ChangeRecorder::new(),
+            last_change: None,
+            relative_line_numbers: false,
+            show_line_numbers: true,
+            scroll_margin: 0,
+            syntax_highlighting: true,
+            buffers: vec![buffer],
+            current_buffer: 0,
+            split: None,
+            split_focus_first: false,
+            split_vertical: false,
+            macros: HashMap::new(),
+            recording_macro: None,
+            last_macro: None,
+            playing_macro: false,
+            failed: false,
+            clipboard: Clipboard::new(),
+            size: termion::terminal_size().unwrap_or((80, 24)),
         }
-        self.mode = Mode::Normal;
-        self.status_message.clear();
-        Ok(false)
     }
 
-    fn insert_char(&mut self, c: char) {
-        let line = &mut self.lines[self.cursor.0];
-        line.insert(self.cursor.1, c);
-        self.cursor.1 += 1;
+    fn apply_config(&mut self, config: Config) {
+        self.tab_width = config.tab_width;
+        self.expand_tab = config.expand_tab;
+        self.auto_indent = config.auto_indent;
+        self.show_line_numbers = config.show_line_numbers;
+        self.relative_line_numbers = config.relative_line_numbers;
+        self.scroll_margin = config.scroll_margin;
     }
 
-    fn insert_newline(&mut self) {
-        let new_line = self.lines[self.cursor.0][self.cursor.1..].to_string();
-        self.lines[self.cursor.0].truncate(self.cursor.1);
-        self.cursor.0 += 1;
-        self.lines.insert(self.cursor.0, new_line);
-        self.cursor.1 = 0;
+    fn buf(&self) -> &Buffer {
+        &self.buffers[self.current_buffer]
     }
 
-    fn delete_char(&mut self) {
-        if self.cursor.1 > 0 {
-            let line = &mut self.lines[self.cursor.0];
-            line.remove(self.cursor.1 - 1);
-            self.cursor.1 -= 1;
-        } else if self.cursor.0 > 0 {
-            let current_line = self.lines.remove(self.cursor.0);
-            self.cursor.0 -= 1;
-            self.cursor.1 = self.lines[self.cursor.0].len();
-            self.lines[self.cursor.0].push_str(&current_line);
-        }
+    fn buf_mut(&mut self) -> &mut Buffer {
+        &mut self.buffers[self.current_buffer]
     }
 
-    fn save(&mut self) -> io::Result<()> {
-        let mut file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open(&self.file_path)?;
+    fn add_buffer(&mut self, file_path: &str) -> io::Result<()> {
+        self.buffers.push(Buffer::open(file_path)?);
+        Ok(())
+    }
+
+    fn switch_buffer(&mut self, index: usize) {
+        self.current_buffer = index;
+        self.status_message = self.file_info();
+    }
 
-        for line in &self.lines {
-            writeln!(file, "{}", line)?;
+    fn edit_file(&mut self, file_path: &str, force: bool) -> bool {
+        if self.buf().modified && !force {
+            self.fail("Unsaved changes; use :e! to force".to_string());
+            return false;
+        }
+        let file_path = if file_path.is_empty() { self.buf().file_path.clone() } else { file_path.to_string() };
+        match Buffer::open(&file_path) {
+            Ok(buffer) => {
+                *self.buf_mut() = buffer;
+                self.status_message = self.file_info();
+                if !Path::new(&file_path).exists() {
+                    self.status_message.push_str(" [New File]");
+                }
+                true
+            }
+            Err(err) => {
+                self.fail(format!("Cannot open \"{}\": {}", file_path, err));
+                false
+            }
         }
-        self.status_message = "File saved".to_string();
-        Ok(())
     }
-}
 
-fn main() -> io::Result<()> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <file_path>", args[0]);
-        return Ok(());
+    fn split_pane(&mut self, file_path: &str, vertical: bool) {
+        if self.split.is_some() {
+            self.fail("Window is already split".to_string());
+            return;
+        }
+        let buffer = if file_path.is_empty() {
+            None
+        } else {
+            match Buffer::open(file_path) {
+                Ok(buffer) => Some(buffer),
+                Err(err) => {
+                    self.fail(format!("Cannot open \"{}\": {}", file_path, err));
+                    return;
+                }
+            }
+        };
+        // Fit the current view into the upper (left) half, then open the new pane below (right of) it.
+        self.split_vertical = vertical;
+        self.split = Some(self.active_pane());
+        self.split_focus_first = true;
+        self.scroll_to_cursor();
+        self.split = Some(self.active_pane());
+        self.split_focus_first = false;
+        if let Some(buffer) = buffer {
+            self.buffers.push(buffer);
+            self.switch_buffer(self.buffers.len() - 1);
+        }
+        self.scroll_to_cursor();
     }
 
-    let mut editor = Editor::new(&args[1])?;
-    editor.run()
-}
\ No newline at end of file
+    fn switch_pane(&mut self) {
+        let Some(pane) = self.split.take() else {
+            return;
+        };
+        self.split = Some(self.active_pane());
+        self.split_focus_first = !self.split_focus_first;
+        self.switch_buffer(pane.buffer);
+        // Both panes may show the same buffer, edited since this one last had the focus.
+        let row = pane.cursor.0.min(self.buf().lines.len() - 1);
+        self.buf_mut().cursor = (row, pane.cursor.1.min(self.buf().lines.line_len(row)));
+        self.buf_mut().scroll_offset = pane.scroll_offset.min(row);
+        self.buf_mut().h_scroll_offset = pane.h_scroll_offset;
+        self.scroll_to_cursor();
+    }
+
+    fn focus_pane(&mut self, direction: char) {
+        let vertical = matches!(direction, 'h' | 'l');
+        let first = matches!(direction, 'h' | 'k');
+        if self.split.is_some() && self.split_vertical == vertical && self.split_focus_first != first {
+            self.switch_pane();
+        }
+    }
+
+    fn close_pane(&mut self) {
+        self.switch_pane();
+        self.split = None;
+        self.scroll_to_cursor();
+    }
+
+    fn file_info(&self) -> String {
+        format!("\"{}\" {}L", self.buf().file_path, self.buf().lines.len())
+    }
+
+    fn next_buffer(&mut self, forward: bool) {
+        let count = self.buffers.len();
+        let index = if forward { self.current_buffer + 1 } else { self.current_buffer + count - 1 };
+        self.switch_buffer(index % count);
+    }
+
+    fn list_buffers(&mut self) {
+        let entries: Vec<String> = self
+            .buffers
+            .iter()
+            .enumerate()
+            .map(|(i, buffer)| {
+                let marker = if i == self.current_buffer { "%" } else { "" };
+                let modified = if buffer.modified { " [+]" } else { "" };
+                format!("{}{} \"{}\"{}", i + 1, marker, buffer.file_path, modified)
+            })
+            .collect();
+        self.status_message = entries.join("  ");
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        let stdout = MouseTerminal::from(io::stdout().into_raw_mode()?);
+        let mut screen = stdout.into_alternate_screen()?;
+        let (sender, events) = mpsc::channel();
+        std::thread::spawn(move || {
+            for event in io::stdin().events() {
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+
+        self.display(&mut screen)?;
+
+        loop {
+            match events.recv_timeout(RESIZE_POLL_INTERVAL) {
+                Ok(event) => match event? {
+                    // Any key dismisses shell output.
+                    Event::Key(_) if !self.overlay.is_empty() => {
+                        self.overlay.clear();
+                        self.status_message.clear();
+                    }
+                    Event::Key(key) => {
+                        if self.handle_key(key)? {
+                            break;
+                        }
+                    }
+                    Event::Mouse(mouse) => self.handle_mouse(mouse),
+                    Event::Unsupported(_) => continue,
+                },
+                Err(RecvTimeoutError::Timeout) if termion::terminal_size()? != self.size => {}
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            self.resize(termion::terminal_size()?);
+            self.display(&mut screen)?;
+        }
+
+        write!(screen, "{}", termion::cursor::Show)?;
+        Ok(())
+    }
+
+    // Keeps the cursor on screen when the terminal grows or shrinks.
+    fn resize(&mut self, size: (u16, u16)) {
+        self.size = size;
+        self.scroll_to_cursor();
+    }
+
+    fn display(&self, screen: &mut impl Write) -> io::Result<()> {
+        write!(screen, "{}{}", termion::clear::All, termion::cursor::Goto(1, 1))?;
+
+        let (active_area, inactive_area) = self.pane_areas();
+        self.draw_pane(screen, &self.view(self.active_pane(), true), active_area)?;
+        if let (Some(pane), Some(area)) = (self.split, inactive_area) {
+            self.draw_pane(screen, &self.view(pane, false), area)?;
+            if self.split_vertical {
+                let column = active_area.left.max(area.left) - 1;
+                for row in 0..area.rows {
+                    write!(screen, "{}{}│", termion::cursor::Goto(column as u16 + 1, row as u16 + 1), color::Fg(color::Reset))?;
+                }
+            }
+        }
+        self.draw_overlay(screen)?;
+        self.draw_status_bar(screen)?;
+
+        // Update cursor position
+        let cursor_y = (active_area.top + self.buf().cursor.0 - self.buf().scroll_offset + 1) as u16;
+        let cursor_col = visual_col(&self.buf().lines.line(self.buf().cursor.0), self.buf().cursor.1, self.tab_width);
+        let cursor_x = (active_area.left + cursor_col - self.buf().h_scroll_offset + self.gutter_width(&self.buf().lines) + 1) as u16;
+        write!(screen, "{}{}", termion::cursor::Goto(cursor_x, cursor_y), termion::cursor::Show)?;
+
+        screen.flush()?;
+        Ok(())
+    }
+
+    // Shell output covers the bottom of the screen, above the status bar.
+    fn draw_overlay(&self, screen: &mut impl Write) -> io::Result<()> {
+        let (width, height) = self.size;
+        let rows = (height as usize).saturating_sub(1);
+        let shown = &self.overlay[self.overlay.len().saturating_sub(rows)..];
+        for (i, line) in shown.iter().enumerate() {
+            let line: String = line.chars().take(width as usize).collect();
+            let row = (rows - shown.len() + i + 1) as u16;
+            write!(screen, "{}{}{}", termion::cursor::Goto(1, row), termion::clear::CurrentLine, line)?;
+        }
+        Ok(())
+    }
+
+    fn draw_pane(&self, screen: &mut impl Write, view: &View, area: Area) -> io::Result<()> {
+        let Area { top, left, rows, cols } = area;
+        let Pane { cursor, scroll_offset, .. } = view.pane;
+        let line_number_width = line_number_width(view.lines);
+        let content_width = cols.saturating_sub(self.gutter_width(view.lines));
+        let search_match = match self.mode {
+            Mode::Search(direction) if view.active => {
+                self.find(&self.status_message, self.buf().cursor, direction).map(|(row, col)| {
+                    (row, col, col + char_len(&self.status_message))
+                })
+            }
+            _ => None,
+        };
+        let bracket_match = match self.mode {
+            Mode::Normal | Mode::Insert if view.active => {
+                find_matching_bracket(view.lines, cursor.0, cursor.1, &HIGHLIGHT_PAIRS, scroll_offset..scroll_offset + rows)
+            }
+            _ => None,
+        };
+
+        for i in scroll_offset..view.lines.len().min(scroll_offset + rows) {
+            let line = view.lines.line(i);
+            // Line number
+            write!(screen, "{}", termion::cursor::Goto(left as u16 + 1, (top + i - scroll_offset + 1) as u16))?;
+            if self.show_line_numbers {
+                let number = if self.relative_line_numbers && i != cursor.0 { i.abs_diff(cursor.0) } else { i + 1 };
+                write!(screen, "{}{:>width$} │ ", color::Fg(color::LightBlue), number, width = line_number_width)?;
+            }
+
+            // Line content
+            write!(screen, "{}", color::Fg(color::Reset))?;
+            let matched = search_match
+                .filter(|m| m.0 == i)
+                .map(|(_, from, to)| (from, to, Highlight::SearchMatch))
+                .or_else(|| bracket_match.filter(|m| m.0 == i).map(|(_, col)| (col, col + 1, Highlight::MatchingBracket)));
+            writeln!(screen, "{}", self.render_line(view, i, &line, content_width, matched))?;
+        }
+
+        // Rows past the end of the file
+        let width = if self.show_line_numbers { line_number_width } else { 1 };
+        for row in view.lines.len().saturating_sub(scroll_offset)..rows {
+            write!(screen, "{}", termion::cursor::Goto(left as u16 + 1, (top + row + 1) as u16))?;
+            write!(screen, "{}{:>width$}{}", color::Fg(color::LightBlack), "~", color::Fg(color::Reset))?;
+        }
+
+        write!(screen, "{}", termion::cursor::Goto(left as u16 + 1, (top + rows + 1) as u16))?;
+        if view.active {
+            write!(screen, "{}", color::Bg(color::Blue))?;
+        } else {
+            write!(screen, "{}", color::Bg(color::LightBlack))?;
+        }
+        write!(
+            screen,
+            "{}{}{}{}",
+            color::Fg(color::White),
+            self.file_status(view, cols, rows),
+            color::Fg(color::Reset),
+            color::Bg(color::Reset)
+        )
+    }
+
+    fn active_pane(&self) -> Pane {
+        Pane {
+            buffer: self.current_buffer,
+            cursor: self.buf().cursor,
+            scroll_offset: self.buf().scroll_offset,
+            h_scroll_offset: self.buf().h_scroll_offset,
+        }
+    }
+
+    fn view(&self, pane: Pane, active: bool) -> View<'_> {
+        let buffer = &self.buffers[pane.buffer];
+        let view = View {
+            lines: &buffer.lines,
+            file_path: &buffer.file_path,
+            modified: buffer.modified,
+            readonly: buffer.readonly,
+            highlighter: buffer.highlighter,
+            pane,
+            active,
+        };
+        // The other pane may show this buffer from before lines were deleted.
+        let last = view.lines.len() - 1;
+        let pane = Pane {
+            cursor: (view.pane.cursor.0.min(last), view.pane.cursor.1),
+            scroll_offset: view.pane.scroll_offset.min(last),
+            ..view.pane
+        };
+        View { pane, ..view }
+    }
+
+    // The screen area of the active pane and, when the window is split, of the
+    // other one. Each pane is followed by its own status line.
+    fn pane_areas(&self) -> (Area, Option<Area>) {
+        let (width, height) = self.size;
+        let (rows, cols) = ((height as usize).saturating_sub(2), width as usize);
+        let whole = Area { top: 0, left: 0, rows, cols };
+        if self.split.is_none() {
+            return (whole, None);
+        }
+        let (first, second) = if self.split_vertical {
+            // One column between the panes holds the separator.
+            let left = cols.saturating_sub(1) / 2;
+            (Area { cols: left, ..whole }, Area { left: left + 1, cols: cols.saturating_sub(left + 1), ..whole })
+        } else {
+            let top = rows.saturating_sub(1) / 2;
+            (Area { rows: top, ..whole }, Area { top: top + 1, rows: rows.saturating_sub(top + 1), ..whole })
+        };
+        if self.split_focus_first {
+            (first, Some(second))
+        } else {
+            (second, Some(first))
+        }
+    }
+
+    fn gutter_width(&self, lines: &Rope) -> usize {
+        if self.show_line_numbers {
+            line_number_width(lines) + 3 // 3 for the separator and padding
+        } else {
+            0
+        }
+    }
+
+    fn render_line(
+        &self,
+        view: &View,
+        row: usize,
+        line: &str,
+        content_width: usize,
+        matched: Option<(usize, usize, Highlight)>,
+    ) -> String {
+        let window_start = view.pane.h_scroll_offset;
+        let window_end = window_start + content_width;
+        let selection = if view.active { self.selection_range(row) } else { None };
+        let mut colors = vec![None; char_len(line)];
+        if self.syntax_highlighting {
+            for (from, to, color) in view.highlighter.highlight_line(line) {
+                colors[from..to].fill(Some(color));
+            }
+        }
+
+        let mut rendered = String::new();
+        let mut vcol = 0;
+        let mut current: Option<Highlight> = None;
+        let mut current_color: Option<color::AnsiValue> = None;
+        for (col, c) in line.chars().enumerate() {
+            let start = vcol;
+            vcol += char_width(c, start, self.tab_width);
+            if vcol <= window_start {
+                continue;
+            }
+            if start >= window_end {
+                break;
+            }
+            let in_range = |from: usize, to: usize| col >= from && col < to;
+            let highlight = if selection.is_some_and(|(from, to)| in_range(from, to)) {
+                Some(Highlight::Selection)
+            } else {
+                matched.filter(|&(from, to, _)| in_range(from, to)).map(|(_, _, highlight)| highlight)
+            };
+            if highlight != current {
+                match highlight {
+                    Some(highlight) => rendered.push_str(&highlight.background()),
+                    None => rendered.push_str(&color::Bg(color::Reset).to_string()),
+                }
+                current = highlight;
+            }
+            let foreground = colors[col];
+            if foreground.map(|c| c.0) != current_color.map(|c| c.0) {
+                match foreground {
+                    Some(foreground) => rendered.push_str(&foreground.fg_string()),
+                    None => rendered.push_str(&color::Fg(color::Reset).to_string()),
+                }
+                current_color = foreground;
+            }
+            if c == '\t' {
+                rendered.push_str(&" ".repeat(vcol.min(window_end) - start.max(window_start)));
+            } else {
+                rendered.push(c);
+            }
+        }
+        if current.is_some() {
+            rendered.push_str(&color::Bg(color::Reset).to_string());
+        }
+        if current_color.is_some() {
+            rendered.push_str(&color::Fg(color::Reset).to_string());
+        }
+        rendered
+    }
+
+    fn draw_status_bar(&self, screen: &mut impl Write) -> io::Result<()> {
+        let (_, height) = self.size;
+        write!(
+            screen,
+            "{}{}{}-- {} -- {}:{} --{}{}{}",
+            termion::cursor::Goto(1, height),
+            color::Bg(color::Blue),
+            color::Fg(color::White),
+            match self.mode {
+                Mode::Normal => "NORMAL",
+                Mode::Insert => "INSERT",
+                Mode::Command => "COMMAND",
+                Mode::Visual => "VISUAL",
+                Mode::VisualLine => "VISUAL LINE",
+                Mode::Search(_) => "SEARCH",
+            },
+            self.buf().cursor.0 + 1,
+            self.buf().cursor.1 + 1,
+            match (self.pending_count, &self.completion) {
+                (Some(count), _) => format!(" {}", count),
+                (None, Some(completion)) => {
+                    // All candidates share the directory the partial name was typed in.
+                    let start = completion.original.rfind(['/', ' ']).map_or(0, |i| i + 1);
+                    let names: Vec<&str> = completion.candidates.iter().map(|candidate| &candidate[start..]).collect();
+                    format!("{}    {}", self.status_message, names.join("  "))
+                }
+                (None, None) => self.status_message.clone(),
+            },
+            color::Fg(color::Reset),
+            color::Bg(color::Reset)
+        )?;
+        Ok(())
+    }
+
+    fn file_status(&self, view: &View, width: usize, visible_lines: usize) -> String {
+        let position = if view.lines.len() <= visible_lines {
+            "All".to_string()
+        } else if view.pane.scroll_offset == 0 {
+            "Top".to_string()
+        } else if view.pane.scroll_offset + visible_lines >= view.lines.len() {
+            "Bot".to_string()
+        } else {
+            format!("{}%", view.pane.scroll_offset * 100 / (view.lines.len() - visible_lines))
+        };
+        let right = format!("{} lines  {} ", view.lines.len(), position);
+        let flags = format!("{}{}", if view.readonly { " [RO]" } else { "" }, if view.modified { " [+]" } else { "" });
+
+        let name = Path::new(view.file_path)
+            .file_name()
+            .map_or_else(|| view.file_path.to_string(), |name| name.to_string_lossy().into_owned());
+        let room = width.saturating_sub(char_len(&right) + flags.len() + 2);
+        let name: String = if char_len(&name) > room {
+            let skip = char_len(&name) - room.saturating_sub(1);
+            std::iter::once('<').chain(name.chars().skip(skip)).take(room).collect()
+        } else {
+            name
+        };
+
+        let left = format!(" {}{}", name, flags);
+        let padding = width.saturating_sub(char_len(&left) + char_len(&right));
+        let status = format!("{}{}{}", left, " ".repeat(padding), right);
+        status.chars().take(width).collect()
+    }
+
+    fn handle_key(&mut self, key: Key) -> io::Result<bool> {
+        if let Some(register) = self.recording_macro.filter(|_| !self.playing_macro) {
+            self.macros.entry(register).or_default().push(key);
+        }
+        if self.recorder.replaying {
+            return self.process_key(key);
+        }
+        if self.is_idle() {
+            self.recorder.start();
+        }
+        self.recorder.keys.push(key);
+        let quit = self.process_key(key)?;
+        if matches!(self.mode, Mode::Command | Mode::Search(_)) {
+            self.recorder.discard = true;
+        }
+        if self.is_idle() {
+            if let Some(keys) = self.recorder.finish() {
+                self.last_change = Some(keys);
+            }
+        }
+        Ok(quit)
+    }
+
+    fn is_idle(&self) -> bool {
+        matches!(self.mode, Mode::Normal) && self.pending_key.is_none() && self.pending_count.is_none()
+    }
+
+    fn start_macro(&mut self, register: char) {
+        self.macros.insert(register, Vec::new());
+        self.recording_macro = Some(register);
+        self.status_message = format!("recording @{}", register);
+    }
+
+    fn stop_macro(&mut self) {
+        if let Some(register) = self.recording_macro.take() {
+            if let Some(keys) = self.macros.get_mut(&register) {
+                keys.pop();
+            }
+        }
+        self.status_message.clear();
+    }
+
+    fn play_macro(&mut self, register: char, count: usize) -> io::Result<bool> {
+        let register = if register == '@' {
+            match self.last_macro {
+                Some(register) => register,
+                None => {
+                    self.fail("No previous macro".to_string());
+                    return Ok(false);
+                }
+            }
+        } else {
+            register
+        };
+        if self.playing_macro {
+            return Ok(false);
+        }
+        let Some(keys) = self.macros.get(&register).cloned() else {
+            self.fail(format!("Register {} is empty", register));
+            return Ok(false);
+        };
+        self.last_macro = Some(register);
+        self.playing_macro = true;
+        self.failed = false;
+        let mut result = Ok(false);
+        'playback: for _ in 0..count {
+            for &key in &keys {
+                result = self.handle_key(key);
+                if self.failed || !matches!(result, Ok(false)) {
+                    break 'playback;
+                }
+            }
+        }
+        self.playing_macro = false;
+        result
+    }
+
+    // Reports an error, which also stops a macro that is playing.
+    fn fail(&mut self, message: String) {
+        self.status_message = message;
+        self.failed = true;
+    }
+
+    fn repeat_last_change(&mut self) -> io::Result<()> {
+        let Some(keys) = self.last_change.clone() else {
+            return Ok(());
+        };
+        self.recorder.replaying = true;
+        let result = keys.into_iter().try_for_each(|key| self.process_key(key).map(|_| ()));
+        self.recorder.replaying = false;
+        self.recorder.changed = false;
+        result
+    }
+
+    fn process_key(&mut self, key: Key) -> io::Result<bool> {
+        if !matches!((&self.mode, key), (Mode::Insert, Key::Char(c)) if c != '\n') {
+            self.buf_mut().undo_history.break_group();
+        }
+        if !matches!(key, Key::Char('\t') | Key::Esc) {
+            self.completion = None;
+        }
+        if self.buf().readonly && self.pending_key.is_none() && is_edit_key(&self.mode, key) {
+            self.pending_count = None;
+            self.fail("File is readonly".to_string());
+            return Ok(false);
+        }
+        match self.mode {
+            Mode::Normal => return self.handle_normal_key(key),
+            Mode::Insert => match key {
+                Key::Esc => self.mode = Mode::Normal,
+                Key::Char('\n') => self.insert_newline(),
+                Key::Char('\t') => self.insert_tab(),
+                Key::Char(c) => self.insert_char(c),
+                Key::Backspace => self.delete_char(),
+                Key::Up => self.move_cursor_up(),
+                Key::Down => self.move_cursor_down(),
+                Key::Left => self.move_cursor_left(),
+                Key::Right => self.move_cursor_right(),
+                _ => {}
+            },
+            Mode::Command | Mode::Search(_) => match key {
+                Key::Char('\t') if matches!(self.mode, Mode::Command) => self.complete_command(),
+                Key::Esc if self.completion.is_some() => {
+                    self.status_message = self.completion.take().unwrap().original;
+                }
+                Key::Char('\n') => {
+                    if let Mode::Search(direction) = self.mode {
+                        self.execute_search(direction);
+                    } else {
+                        return self.execute_command();
+                    }
+                }
+                Key::Esc => {
+                    self.mode = Mode::Normal;
+                    self.status_message.clear();
+                }
+                Key::Char(c) => self.status_message.push(c),
+                Key::Backspace => { self.status_message.pop(); }
+                Key::Up if matches!(self.mode, Mode::Command) => self.recall_command(true),
+                Key::Down if matches!(self.mode, Mode::Command) => self.recall_command(false),
+                _ => {}
+            },
+            Mode::Visual => match key {
+                Key::Esc => self.exit_visual_mode(),
+                Key::Char('d') => self.delete_selection(),
+                Key::Char('y') => self.yank_selection(),
+                Key::Char('~') => self.toggle_selection_case(),
+                key => self.move_by(key, 1),
+            },
+            Mode::VisualLine => match key {
+                Key::Esc => self.exit_visual_mode(),
+                Key::Char('d') => {
+                    if let Some((first, last)) = self.selected_lines() {
+                        self.delete_lines(first, last);
+                    }
+                    self.exit_visual_mode();
+                }
+                Key::Char('y') => {
+                    if let Some((first, last)) = self.selected_lines() {
+                        self.yank_lines(first, last);
+                        self.buf_mut().cursor = (first, self.buf().cursor.1.min(self.buf().lines.line_len(first)));
+                        self.scroll_to_cursor();
+                    }
+                    self.exit_visual_mode();
+                }
+                Key::Char(c @ ('>' | '<')) => {
+                    if let Some((first, last)) = self.selected_lines() {
+                        self.buf_mut().cursor.0 = first;
+                        self.shift_lines(first, last, c == '>');
+                    }
+                    self.exit_visual_mode();
+                }
+                Key::Char('k') | Key::Up => self.move_cursor_up(),
+                Key::Char('j') | Key::Down => self.move_cursor_down(),
+                _ => {}
+            },
+        }
+        Ok(false)
+    }
+
+    fn handle_normal_key(&mut self, key: Key) -> io::Result<bool> {
+        if let Some(pending) = self.pending_key.take() {
+            let count = self.pending_count.take();
+            match (pending, key) {
+                ('d', Key::Char('d')) => self.delete_line(count.unwrap_or(1)),
+                ('y', Key::Char('y')) => {
+                    let last = (self.buf().cursor.0 + count.unwrap_or(1) - 1).min(self.buf().lines.len() - 1);
+                    self.yank_lines(self.buf().cursor.0, last);
+                }
+                ('c', Key::Char('w')) => self.change_word(),
+                ('c', Key::Char('c')) => self.change_line(count.unwrap_or(1)),
+                ('c', Key::Char('$')) => self.change_to_line_end(),
+                ('d', Key::Char('$')) => self.delete_to_line_end(),
+                ('r', Key::Char(c)) => self.replace_char(c),
+                (kind @ ('f' | 'F' | 't' | 'T'), Key::Char(c)) => {
+                    self.last_find = Some((kind, c));
+                    self.find_char(kind, c, count.unwrap_or(1), false);
+                }
+                ('q', Key::Char(c)) if c.is_ascii_lowercase() => self.start_macro(c),
+                ('@', Key::Char(c)) if c.is_ascii_lowercase() || c == '@' => {
+                    return self.play_macro(c, count.unwrap_or(1));
+                }
+                ('m', Key::Char(c)) => self.set_mark(c),
+                ('\'', Key::Char(c)) => self.jump_to_mark(c, false),
+                ('`', Key::Char(c)) => self.jump_to_mark(c, true),
+                (c @ ('>' | '<'), Key::Char(k)) if k == c => {
+                    let last = (self.buf().cursor.0 + count.unwrap_or(1) - 1).min(self.buf().lines.len() - 1);
+                    self.shift_lines(self.buf().cursor.0, last, c == '>');
+                }
+                ('z', Key::Char('z')) => self.set_scroll_offset(self.buf().cursor.0.saturating_sub(self.visible_rows() / 2)),
+                ('z', Key::Char('t')) => self.set_scroll_offset(self.buf().cursor.0),
+                ('z', Key::Char('b')) => self.set_scroll_offset((self.buf().cursor.0 + 1).saturating_sub(self.visible_rows())),
+                ('g', Key::Char('g')) => self.goto_line(count.unwrap_or(1).saturating_sub(1)),
+                (CTRL_W, Key::Ctrl('w') | Key::Char('w')) => self.switch_pane(),
+                (CTRL_W, Key::Char(c @ ('h' | 'j' | 'k' | 'l'))) => self.focus_pane(c),
+                _ => {}
+            }
+            return Ok(false);
+        }
+
+        if let Key::Char(c @ '0'..='9') = key {
+            if c != '0' || self.pending_count.is_some() {
+                let digit = c.to_digit(10).unwrap() as usize;
+                let count = self.pending_count.unwrap_or(0);
+                self.pending_count = Some((count * 10 + digit).min(MAX_COUNT));
+                return Ok(false);
+            }
+        }
+
+        let count = self.pending_count.unwrap_or(1);
+        match key {
+            Key::Char('q') if self.recording_macro.is_some() => self.stop_macro(),
+            Key::Char(c @ ('q' | '@')) => self.pending_key = Some(c),
+            Key::Char('i') => self.mode = Mode::Insert,
+            Key::Char('a') => {
+                let buf = self.buf_mut();
+                buf.cursor.1 = (buf.cursor.1 + 1).min(buf.lines.line_len(buf.cursor.0));
+                self.mode = Mode::Insert;
+            }
+            Key::Char('A') => {
+                let buf = self.buf_mut();
+                buf.cursor.1 = buf.lines.line_len(buf.cursor.0);
+                self.mode = Mode::Insert;
+            }
+            Key::Char('I') => {
+                let buf = self.buf_mut();
+                buf.cursor.1 = first_non_blank(&buf.lines.line(buf.cursor.0));
+                self.mode = Mode::Insert;
+            }
+            Key::Char(':') => {
+                self.mode = Mode::Command;
+                self.status_message.clear();
+                self.history_pos = None;
+            },
+            Key::Char('/') => {
+                self.mode = Mode::Search(SearchDirection::Forward);
+                self.status_message.clear();
+            }
+            Key::Char('?') => {
+                self.mode = Mode::Search(SearchDirection::Backward);
+                self.status_message.clear();
+            }
+            Key::Char('n') => self.search_next(self.search_direction),
+            Key::Char('N') => self.search_next(self.search_direction.reversed()),
+            Key::Char('v') => {
+                self.mode = Mode::Visual;
+                self.visual_start = Some(self.buf().cursor);
+            }
+            Key::Char('V') => {
+                self.mode = Mode::VisualLine;
+                self.visual_start = Some(self.buf().cursor);
+            }
+            Key::Char(c @ ('m' | '\'' | '`')) => self.pending_key = Some(c),
+            Key::Char('g') => self.pending_key = Some('g'),
+            Key::Char('z') => self.pending_key = Some('z'),
+            Key::Ctrl('w') => self.pending_key = Some(CTRL_W),
+            Key::Char('r') => self.pending_key = Some('r'),
+            Key::Char('%') => self.jump_to_matching_bracket(),
+            Key::Char(c @ (';' | ',')) => {
+                if let Some((kind, target)) = self.last_find {
+                    let kind = match (c, kind) {
+                        (';', kind) => kind,
+                        (_, 'f') => 'F',
+                        (_, 'F') => 'f',
+                        (_, 't') => 'T',
+                        _ => 't',
+                    };
+                    self.find_char(kind, target, count, true);
+                }
+            }
+            Key::Char(c @ ('f' | 'F' | 't' | 'T')) => self.pending_key = Some(c),
+            Key::Char(c @ ('>' | '<')) => self.pending_key = Some(c),
+            Key::Char('G') => {
+                let row = self.pending_count.map_or(self.buf().lines.len() - 1, |count| count.saturating_sub(1));
+                self.goto_line(row);
+            }
+            Key::Char('c') => self.pending_key = Some('c'),
+            Key::Char('d') => self.pending_key = Some('d'),
+            Key::Char('y') => self.pending_key = Some('y'),
+            Key::Char('o') => self.open_line(true),
+            Key::Char('O') => self.open_line(false),
+            Key::Char('x') => self.delete_chars(count),
+            Key::Char('J') => self.join_lines(count),
+            Key::Char('D') => self.delete_to_line_end(),
+            Key::Char('C') => self.change_to_line_end(),
+            Key::Char('S') => self.change_line(count),
+            Key::Char('~') => self.toggle_case(count),
+            Key::Char('p') => self.paste(true, count),
+            Key::Char('P') => self.paste(false, count),
+            Key::Char('.') => self.repeat_last_change()?,
+            Key::Char('u') => self.undo(),
+            Key::Ctrl('r') => self.redo(),
+            Key::Ctrl('a') => self.add_to_number(count as i64),
+            Key::Ctrl('x') => self.add_to_number(-(count as i64)),
+            Key::Ctrl('d') => self.scroll_screen((self.page_height() / 2).max(1), true),
+            Key::Ctrl('u') => self.scroll_screen((self.page_height() / 2).max(1), false),
+            Key::Ctrl('f') => self.scroll_screen(self.page_height(), true),
+            Key::Ctrl('b') => self.scroll_screen(self.page_height(), false),
+            Key::Ctrl('o') => self.jump_back(),
+            Key::Ctrl('i') | Key::Char('\t') => self.jump_forward(),
+            key => self.move_by(key, count),
+        }
+        if self.pending_key.is_none() {
+            self.pending_count = None;
+        }
+        Ok(false)
+    }
+
+    fn move_by(&mut self, key: Key, count: usize) {
+        match key {
+            Key::Char('0') => self.buf_mut().cursor.1 = 0,
+            Key::Char('^') => self.buf_mut().cursor.1 = first_non_blank(&self.buf().lines.line(self.buf().cursor.0)),
+            Key::Char('$') => self.move_to_line_end(),
+            Key::Char('w') => self.repeat_motion(count, Editor::word_forward),
+            Key::Char('b') => self.repeat_motion(count, Editor::word_backward),
+            Key::Char('e') => self.repeat_motion(count, Editor::word_end),
+            Key::Char('k') | Key::Up => self.repeat_motion(count, Editor::move_cursor_up),
+            Key::Char('j') | Key::Down => self.repeat_motion(count, Editor::move_cursor_down),
+            Key::Char('h') | Key::Left => self.repeat_motion(count, Editor::move_cursor_left),
+            Key::Char('l') | Key::Right => self.repeat_motion(count, Editor::move_cursor_right),
+            _ => {}
+        }
+    }
+
+    // Stops early once the cursor can go no further, so a huge count is cheap.
+    // A motion that cannot move at all has failed.
+    fn repeat_motion(&mut self, count: usize, motion: fn(&mut Editor)) {
+        for i in 0..count {
+            let before = self.buf().cursor;
+            motion(self);
+            if self.buf().cursor == before {
+                self.failed |= i == 0;
+                break;
+            }
+        }
+    }
+
+    fn move_cursor_up(&mut self) {
+        if self.buf().cursor.0 > 0 {
+            self.buf_mut().cursor.0 -= 1;
+            let buf = self.buf_mut();
+            buf.cursor.1 = buf.cursor.1.min(buf.lines.line_len(buf.cursor.0));
+            if self.buf().cursor.0 < self.buf().scroll_offset {
+                self.buf_mut().scroll_offset = self.buf().cursor.0;
+            }
+        }
+    }
+
+    fn move_cursor_down(&mut self) {
+        if self.buf().cursor.0 < self.buf().lines.len() - 1 {
+            self.buf_mut().cursor.0 += 1;
+            let buf = self.buf_mut();
+            buf.cursor.1 = buf.cursor.1.min(buf.lines.line_len(buf.cursor.0));
+            let page = self.page_height();
+            if self.buf().cursor.0 >= self.buf().scroll_offset + page {
+                self.buf_mut().scroll_offset = self.buf().cursor.0.saturating_sub(page);
+            }
+        }
+    }
+
+    fn move_cursor_left(&mut self) {
+        if self.buf().cursor.1 > 0 {
+            self.buf_mut().cursor.1 -= 1;
+        } else if self.buf().cursor.0 > 0 {
+            self.buf_mut().cursor.0 -= 1;
+            let buf = self.buf_mut();
+            buf.cursor.1 = buf.lines.line_len(buf.cursor.0);
+        }
+    }
+
+    fn move_cursor_right(&mut self) {
+        if self.buf().cursor.1 < self.buf().lines.line_len(self.buf().cursor.0) {
+            self.buf_mut().cursor.1 += 1;
+        } else if self.buf().cursor.0 < self.buf().lines.len() - 1 {
+            self.buf_mut().cursor.0 += 1;
+            self.buf_mut().cursor.1 = 0;
+        }
+    }
+
+    fn goto_line(&mut self, row: usize) {
+        self.push_jump();
+        self.move_to((row, self.buf().cursor.1));
+    }
+
+    fn move_to(&mut self, (row, col): (usize, usize)) {
+        let buf = self.buf_mut();
+        buf.cursor.0 = row.min(buf.lines.len() - 1);
+        buf.cursor.1 = col.min(buf.lines.line_len(buf.cursor.0));
+        let page = self.page_height();
+        if self.buf().cursor.0 < self.buf().scroll_offset || self.buf().cursor.0 >= self.buf().scroll_offset + page {
+            self.buf_mut().scroll_offset = self.buf().cursor.0.saturating_sub(page / 2);
+        }
+        self.scroll_to_cursor();
+    }
+
+    fn set_mark(&mut self, mark: char) {
+        if mark.is_ascii_lowercase() {
+            let buf = self.buf_mut();
+            buf.marks.insert(mark, buf.cursor);
+        } else {
+            self.fail(format!("Invalid mark: {}", mark));
+        }
+    }
+
+    fn jump_to_mark(&mut self, mark: char, exact: bool) {
+        if !mark.is_ascii_lowercase() {
+            self.fail(format!("Invalid mark: {}", mark));
+            return;
+        }
+        match self.buf().marks.get(&mark).copied() {
+            Some((row, col)) => {
+                self.push_jump();
+                self.move_to((row, if exact { col } else { 0 }));
+            }
+            None => self.fail(format!("Mark not set: {}", mark)),
+        }
+    }
+
+    fn push_jump(&mut self) {
+        let buf = self.buf_mut();
+        buf.jump_list.truncate(buf.jump_pos);
+        buf.jump_list.push(buf.cursor);
+        if self.buf().jump_list.len() > JUMP_LIST_DEPTH {
+            self.buf_mut().jump_list.remove(0);
+        }
+        self.buf_mut().jump_pos = self.buf().jump_list.len();
+    }
+
+    fn jump_back(&mut self) {
+        if self.buf().jump_pos == 0 {
+            return;
+        }
+        if self.buf().jump_pos == self.buf().jump_list.len() {
+            let buf = self.buf_mut();
+            buf.jump_list.push(buf.cursor);
+            if buf.jump_list.len() > JUMP_LIST_DEPTH {
+                buf.jump_list.remove(0);
+                buf.jump_pos -= 1;
+            }
+        }
+        self.buf_mut().jump_pos -= 1;
+        self.move_to(self.buf().jump_list[self.buf().jump_pos]);
+    }
+
+    fn jump_forward(&mut self) {
+        if self.buf().jump_pos + 1 < self.buf().jump_list.len() {
+            self.buf_mut().jump_pos += 1;
+            self.move_to(self.buf().jump_list[self.buf().jump_pos]);
+        }
+    }
+
+    fn jump_to_matching_bracket(&mut self) {
+        let (row, col) = self.buf().cursor;
+        let lines = &self.buf().lines;
+        let is_bracket = |c: char| BRACKET_PAIRS.iter().any(|&(open, close)| c == open || c == close);
+        let found = lines
+            .line(row)
+            .chars()
+            .enumerate()
+            .skip(col)
+            .find(|&(_, c)| is_bracket(c))
+            .and_then(|(col, _)| find_matching_bracket(lines, row, col, &BRACKET_PAIRS, 0..lines.len()));
+        match found {
+            Some(position) => {
+                self.push_jump();
+                self.move_to(position);
+            }
+            None => self.fail("No matching bracket".to_string()),
+        }
+    }
+
+    fn find_char(&mut self, kind: char, target: char, count: usize, repeat: bool) {
+        let chars: Vec<char> = self.buf().lines.line(self.buf().cursor.0).chars().collect();
+        let col = self.buf().cursor.1;
+        // Repeating t/T from just beside a match would find that same match again.
+        let skip = usize::from(repeat && (kind == 't' || kind == 'T'));
+        let found = if kind == 'f' || kind == 't' {
+            (col + 1 + skip..chars.len()).filter(|&i| chars[i] == target).nth(count - 1)
+        } else {
+            (0..col.saturating_sub(skip).min(chars.len())).rev().filter(|&i| chars[i] == target).nth(count - 1)
+        };
+        match found {
+            Some(found) => {
+                self.buf_mut().cursor.1 = match kind {
+                    't' => found - 1,
+                    'T' => found + 1,
+                    _ => found,
+                }
+            }
+            None => self.failed = true,
+        }
+    }
+
+    fn move_to_line_end(&mut self) {
+        let buf = self.buf_mut();
+        buf.cursor.1 = buf.lines.line_len(buf.cursor.0).saturating_sub(1);
+    }
+
+    fn char_at(&self, (row, col): (usize, usize)) -> char {
+        self.buf().lines.line(row).chars().nth(col).unwrap_or('\n')
+    }
+
+    fn next_pos(&self, (row, col): (usize, usize)) -> Option<(usize, usize)> {
+        if col < self.buf().lines.line_len(row) {
+            Some((row, col + 1))
+        } else if row + 1 < self.buf().lines.len() {
+            Some((row + 1, 0))
+        } else {
+            None
+        }
+    }
+
+    fn prev_pos(&self, (row, col): (usize, usize)) -> Option<(usize, usize)> {
+        if col > 0 {
+            Some((row, col - 1))
+        } else if row > 0 {
+            Some((row - 1, self.buf().lines.line_len(row - 1)))
+        } else {
+            None
+        }
+    }
+
+    fn is_empty_line(&self, (row, _): (usize, usize)) -> bool {
+        self.buf().lines.line_len(row) == 0
+    }
+
+    fn word_forward(&mut self) {
+        let mut pos = self.buf().cursor;
+        let class = char_class(self.char_at(pos));
+        if class != 0 {
+            while char_class(self.char_at(pos)) == class {
+                match self.next_pos(pos) {
+                    Some(next) => pos = next,
+                    None => break,
+                }
+            }
+        }
+        while char_class(self.char_at(pos)) == 0 && !(pos != self.buf().cursor && self.is_empty_line(pos)) {
+            match self.next_pos(pos) {
+                Some(next) => pos = next,
+                None => break,
+            }
+        }
+        self.buf_mut().cursor = pos;
+        self.scroll_to_cursor();
+    }
+
+    fn word_backward(&mut self) {
+        let Some(mut pos) = self.prev_pos(self.buf().cursor) else {
+            return;
+        };
+        while char_class(self.char_at(pos)) == 0 && !self.is_empty_line(pos) {
+            match self.prev_pos(pos) {
+                Some(prev) => pos = prev,
+                None => break,
+            }
+        }
+        let class = char_class(self.char_at(pos));
+        if class != 0 {
+            while let Some(prev) = self.prev_pos(pos) {
+                if char_class(self.char_at(prev)) != class {
+                    break;
+                }
+                pos = prev;
+            }
+        }
+        self.buf_mut().cursor = pos;
+        self.scroll_to_cursor();
+    }
+
+    fn word_end(&mut self) {
+        let Some(mut pos) = self.next_pos(self.buf().cursor) else {
+            return;
+        };
+        while char_class(self.char_at(pos)) == 0 {
+            match self.next_pos(pos) {
+                Some(next) => pos = next,
+                None => return,
+            }
+        }
+        let class = char_class(self.char_at(pos));
+        while let Some(next) = self.next_pos(pos) {
+            if char_class(self.char_at(next)) != class {
+                break;
+            }
+            pos = next;
+        }
+        self.buf_mut().cursor = pos;
+        self.scroll_to_cursor();
+    }
+
+    fn find_forward(&self, query: &str, from: (usize, usize)) -> Option<(usize, usize)> {
+        if query.is_empty() {
+            return None;
+        }
+        let line_count = self.buf().lines.len();
+        for i in 0..=line_count {
+            let row = (from.0 + i) % line_count;
+            let line = self.buf().lines.line(row);
+            let start = if i == 0 { byte_index(&line, from.1 + 1) } else { 0 };
+            if let Some(offset) = line[start..].find(query) {
+                return Some((row, char_len(&line[..start + offset])));
+            }
+        }
+        None
+    }
+
+    fn find_backward(&self, query: &str, from: (usize, usize)) -> Option<(usize, usize)> {
+        if query.is_empty() {
+            return None;
+        }
+        let line_count = self.buf().lines.len();
+        for i in 0..=line_count {
+            let row = (from.0 + line_count - i % line_count) % line_count;
+            let line = self.buf().lines.line(row);
+            let end = if i == 0 { byte_index(&line, from.1) } else { line.len() };
+            let found = line
+                .match_indices(query)
+                .map(|(offset, _)| offset)
+                .filter(|&offset| i != 0 || offset < end)
+                .last();
+            if let Some(offset) = found {
+                return Some((row, char_len(&line[..offset])));
+            }
+        }
+        None
+    }
+
+    fn find(&self, query: &str, from: (usize, usize), direction: SearchDirection) -> Option<(usize, usize)> {
+        match direction {
+            SearchDirection::Forward => self.find_forward(query, from),
+            SearchDirection::Backward => self.find_backward(query, from),
+        }
+    }
+
+    fn execute_search(&mut self, direction: SearchDirection) {
+        let query = std::mem::take(&mut self.status_message);
+        self.mode = Mode::Normal;
+        let query = if query.is_empty() {
+            match self.last_search.clone() {
+                Some(query) => query,
+                None => return,
+            }
+        } else {
+            query
+        };
+        self.search(&query, direction);
+        self.last_search = Some(query);
+        self.search_direction = direction;
+    }
+
+    fn search_next(&mut self, direction: SearchDirection) {
+        match self.last_search.clone() {
+            Some(query) => self.search(&query, direction),
+            None => self.fail("No previous search pattern".to_string()),
+        }
+    }
+
+    fn search(&mut self, query: &str, direction: SearchDirection) {
+        match self.find(query, self.buf().cursor, direction) {
+            Some(pos) => {
+                let forward = direction == SearchDirection::Forward;
+                self.status_message = if forward && pos <= self.buf().cursor {
+                    "search hit BOTTOM, continuing at TOP".to_string()
+                } else if !forward && pos >= self.buf().cursor {
+                    "search hit TOP, continuing at BOTTOM".to_string()
+                } else {
+                    format!("{}{}", if forward { '/' } else { '?' }, query)
+                };
+                self.push_jump();
+                self.move_to(pos);
+            }
+            None => self.fail("Pattern not found".to_string()),
+        }
+    }
+
+    fn complete_command(&mut self) {
+        if let Some(completion) = &mut self.completion {
+            completion.index = (completion.index + 1) % completion.candidates.len();
+            self.status_message = completion.candidates[completion.index].clone();
+            return;
+        }
+        let Some(space) = self.status_message.find(' ') else {
+            return;
+        };
+        let (command, partial) = self.status_message.split_at(space + 1);
+        if !matches!(command, "e " | "e! " | "w " | "sp " | "vsp ") {
+            return;
+        }
+        let candidates: Vec<String> = complete_path(partial).into_iter().map(|path| format!("{}{}", command, path)).collect();
+        match candidates.len() {
+            0 => {}
+            1 => self.status_message = candidates[0].clone(),
+            _ => {
+                let original = std::mem::replace(&mut self.status_message, candidates[0].clone());
+                self.completion = Some(Completion { original, candidates, index: 0 });
+            }
+        }
+    }
+
+    fn recall_command(&mut self, older: bool) {
+        let last = match self.command_history.len() {
+            0 => return,
+            len => len - 1,
+        };
+        self.history_pos = match (self.history_pos, older) {
+            (None, true) => Some(last),
+            (Some(pos), true) => Some(pos.saturating_sub(1)),
+            (Some(pos), false) if pos < last => Some(pos + 1),
+            (_, false) => None,
+        };
+        self.status_message = self.history_pos.map_or_else(String::new, |pos| self.command_history[pos].clone());
+    }
+
+    fn execute_command(&mut self) -> io::Result<bool> {
+        let input = std::mem::take(&mut self.status_message);
+        self.mode = Mode::Normal;
+        self.failed = false;
+        let quit = self.run_command(&input)?;
+        // Only commands that worked are worth recalling.
+        if !self.failed && !input.is_empty() && self.command_history.last() != Some(&input) {
+            self.command_history.push(input);
+        }
+        Ok(quit)
+    }
+
+    fn run_command(&mut self, input: &str) -> io::Result<bool> {
+        if let Ok(line) = input.parse::<usize>() {
+            self.push_jump();
+            self.move_to((line.saturating_sub(1), 0));
+            return Ok(false);
+        }
+        let (range, command) = match self.parse_range(input) {
+            Ok(parsed) => parsed,
+            Err(message) => {
+                self.fail(message);
+                return Ok(false);
+            }
+        };
+        match (range, command) {
+            (None, "w" | "wq" | "x") if self.buf().readonly => {
+                self.fail("File is readonly; use :w! to force".to_string());
+            }
+            (_, cmd) if self.buf().readonly && (cmd == "s" || cmd.starts_with("s/")) => {
+                self.fail("File is readonly".to_string());
+            }
+            (Some(_), cmd) if self.buf().readonly && cmd.starts_with('!') => {
+                self.fail("File is readonly".to_string());
+            }
+            (None, "w" | "w!") => {
+                self.save();
+            }
+            (None, cmd) if cmd.starts_with("w ") => self.save_as(cmd["w ".len()..].trim()),
+            (None, "q" | "q!") if self.split.is_some() => self.close_pane(),
+            (None, "wq" | "x") if self.split.is_some() => {
+                if self.save() {
+                    self.close_pane();
+                }
+            }
+            (None, "q") => return Ok(self.try_quit()),
+            (None, "q!") => return Ok(true),
+            (None, "wq" | "x") => return Ok(self.save()),
+            (None, "bn" | "bnext") => self.next_buffer(true),
+            (None, "bp" | "bprevious") => self.next_buffer(false),
+            (None, "ls" | "buffers") => self.list_buffers(),
+            (None, cmd) if cmd == "e" || cmd == "e!" || cmd.starts_with("e ") || cmd.starts_with("e! ") => {
+                let (force, file_path) = match cmd.strip_prefix("e!") {
+                    Some(file_path) => (true, file_path),
+                    None => (false, &cmd[1..]),
+                };
+                self.edit_file(file_path.trim(), force);
+            }
+            (None, cmd) if cmd == "view" || cmd.starts_with("view ") => {
+                if self.edit_file(cmd["view".len()..].trim(), false) {
+                    self.buf_mut().readonly = true;
+                }
+            }
+            (None, cmd) if cmd == "sp" || cmd.starts_with("sp ") => self.split_pane(cmd[2..].trim(), false),
+            (None, cmd) if cmd == "vsp" || cmd.starts_with("vsp ") => self.split_pane(cmd[3..].trim(), true),
+            (None, cmd) if cmd.starts_with('!') => self.run_shell(&cmd[1..]),
+            (Some((first, last)), cmd) if cmd.starts_with('!') => self.filter_lines(first, last, &cmd[1..]),
+            (None, cmd) if cmd.starts_with("set ") => self.set_option(cmd["set ".len()..].trim()),
+            (range, cmd) if cmd == "s" || cmd.starts_with("s/") => {
+                let (first, last) = range.unwrap_or((self.buf().cursor.0, self.buf().cursor.0));
+                self.substitute(cmd.strip_prefix("s/").unwrap_or(""), first, last);
+            }
+            _ => self.fail("Invalid command".to_string()),
+        }
+        Ok(false)
+    }
+
+    fn run_shell(&mut self, command: &str) {
+        let output = match process::Command::new("sh").arg("-c").arg(command).stdin(Stdio::null()).output() {
+            Ok(output) => output,
+            Err(err) => {
+                self.fail(format!("Cannot run \"{}\": {}", command, err));
+                return;
+            }
+        };
+        let mut lines: Vec<String> = String::from_utf8_lossy(&output.stdout).lines().map(String::from).collect();
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let mut message = match output.status.code() {
+                Some(code) => format!("shell returned {}", code),
+                None => "shell terminated by signal".to_string(),
+            };
+            if let Some(line) = stderr.lines().find(|line| !line.trim().is_empty()) {
+                message = format!("{}: {}", message, line);
+            }
+            self.fail(message);
+        } else if lines.len() <= 1 {
+            self.status_message = lines.pop().unwrap_or_default();
+            return;
+        } else {
+            self.status_message = "Press any key to continue".to_string();
+        }
+        if lines.len() > 1 {
+            self.overlay = lines;
+        }
+    }
+
+    fn filter_lines(&mut self, first: usize, last: usize, command: &str) {
+        let mut input = (first..=last).map(|row| self.buf().lines.line(row)).collect::<Vec<_>>().join("\n");
+        input.push('\n');
+        let child = process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) => {
+                self.fail(format!("Cannot run \"{}\": {}", command, err));
+                return;
+            }
+        };
+        // Feed stdin from another thread so a command that writes before it
+        // has read everything cannot deadlock us.
+        let mut stdin = child.stdin.take().unwrap();
+        let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+        let output = child.wait_with_output();
+        let _ = writer.join();
+        let output = match output {
+            Ok(output) => output,
+            Err(err) => {
+                self.fail(format!("Cannot run \"{}\": {}", command, err));
+                return;
+            }
+        };
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let mut message = match output.status.code() {
+                Some(code) => format!("shell returned {}, buffer unchanged", code),
+                None => "shell terminated by signal, buffer unchanged".to_string(),
+            };
+            if let Some(line) = stderr.lines().find(|line| !line.trim().is_empty()) {
+                message = format!("{}: {}", message, line);
+            }
+            self.fail(message);
+            return;
+        }
+
+        let lines: Vec<String> = String::from_utf8_lossy(&output.stdout).lines().map(String::from).collect();
+        self.begin_edit();
+        self.buf_mut().lines.splice(first, last, &lines);
+        let count = lines.len();
+        self.buf_mut().cursor = (first.min(self.buf().lines.len() - 1), 0);
+        self.status_message = format!("{} lines filtered into {}", last - first + 1, count);
+    }
+
+    fn set_option(&mut self, option: &str) {
+        match option {
+            "number" | "nu" => self.show_line_numbers = true,
+            "nonumber" | "nonu" => self.show_line_numbers = false,
+            "relativenumber" => self.relative_line_numbers = !self.relative_line_numbers,
+            "norelativenumber" => self.relative_line_numbers = false,
+            "highlight" => self.syntax_highlighting = true,
+            "nohighlight" => self.syntax_highlighting = false,
+            "autoindent" | "ai" => self.auto_indent = true,
+            "noautoindent" | "noai" => self.auto_indent = false,
+            "expandtab" | "et" => self.expand_tab = true,
+            "noexpandtab" | "noet" => self.expand_tab = false,
+            "readonly" | "ro" => self.buf_mut().readonly = true,
+            "noreadonly" | "noro" => self.buf_mut().readonly = false,
+            "trimtrailing" => self.trim_trailing = true,
+            "notrimtrailing" => self.trim_trailing = false,
+            "fileformat=unix" | "ff=unix" => self.buf_mut().line_ending = LineEnding::Lf,
+            "fileformat=dos" | "ff=dos" => self.buf_mut().line_ending = LineEnding::CrLf,
+            _ => self.fail(format!("Unknown option: {}", option)),
+        }
+    }
+
+    fn parse_range<'a>(&self, command: &'a str) -> Result<(Option<LineRange>, &'a str), String> {
+        if let Some(rest) = command.strip_prefix('%') {
+            return Ok((Some((0, self.buf().lines.len() - 1)), rest));
+        }
+        let (first, rest) = match split_number(command) {
+            Some(parsed) => parsed,
+            None => return Ok((None, command)),
+        };
+        let (last, rest) = match rest.strip_prefix(',') {
+            Some(rest) => split_number(rest).ok_or_else(|| "Invalid range".to_string())?,
+            None => (first, rest),
+        };
+        if first == 0 || first > last || last > self.buf().lines.len() {
+            return Err("Invalid range".to_string());
+        }
+        Ok((Some((first - 1, last - 1)), rest))
+    }
+
+    fn substitute(&mut self, args: &str, first: usize, last: usize) {
+        let parts = split_substitute(args);
+        if parts.len() < 2 || parts.len() > 3 {
+            self.fail("Usage: s/pattern/replacement/[g]".to_string());
+            return;
+        }
+        let (pattern, replacement) = (parts[0].as_str(), parts[1].as_str());
+        let global = match parts.get(2).map_or("", |flags| flags.as_str()) {
+            "" => false,
+            "g" => true,
+            flags => {
+                self.fail(format!("Invalid flags: {}", flags));
+                return;
+            }
+        };
+        if pattern.is_empty() {
+            self.fail("Empty search pattern".to_string());
+            return;
+        }
+
+        let matches: Vec<(usize, usize)> = (first..=last)
+            .map(|row| {
+                let line = self.buf().lines.line(row);
+                let count = if global { line.matches(pattern).count() } else { line.contains(pattern) as usize };
+                (row, count)
+            })
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        if matches.is_empty() {
+            self.fail(format!("Pattern not found: {}", pattern));
+            return;
+        }
+
+        self.begin_edit();
+        for &(row, _) in &matches {
+            let line = self.buf().lines.line(row);
+            let line = if global { line.replace(pattern, replacement) } else { line.replacen(pattern, replacement, 1) };
+            self.buf_mut().lines.set_line(row, &line);
+        }
+        let buf = self.buf_mut();
+        buf.cursor.1 = buf.cursor.1.min(buf.lines.line_len(buf.cursor.0));
+
+        let total: usize = matches.iter().map(|&(_, count)| count).sum();
+        self.status_message = format!(
+            "{} substitution{} on {} line{}",
+            total,
+            if total == 1 { "" } else { "s" },
+            matches.len(),
+            if matches.len() == 1 { "" } else { "s" }
+        );
+    }
+
+    fn exit_visual_mode(&mut self) {
+        self.mode = Mode::Normal;
+        self.visual_start = None;
+    }
+
+    fn selection_bounds(&self) -> Option<((usize, usize), (usize, usize))> {
+        let start = self.visual_start?;
+        if start <= self.buf().cursor {
+            Some((start, self.buf().cursor))
+        } else {
+            Some((self.buf().cursor, start))
+        }
+    }
+
+    fn selected_lines(&self) -> Option<(usize, usize)> {
+        let (start, end) = self.selection_bounds()?;
+        Some((start.0, end.0))
+    }
+
+    fn selection_range(&self, row: usize) -> Option<(usize, usize)> {
+        if let Mode::VisualLine = self.mode {
+            let (first, last) = self.selected_lines()?;
+            return (first..=last).contains(&row).then(|| (0, self.buf().lines.line_len(row)));
+        }
+        let (start, end) = self.selection_bounds()?;
+        if row < start.0 || row > end.0 {
+            return None;
+        }
+        let len = self.buf().lines.line_len(row);
+        let from = if row == start.0 { start.1 } else { 0 };
+        let to = if row == end.0 { end.1 + 1 } else { len };
+        Some((from.min(len), to.min(len)))
+    }
+
+    fn selected_text(&self) -> Option<String> {
+        let (start, end) = self.selection_bounds()?;
+        let text = (start.0..=end.0)
+            .map(|row| {
+                let line = self.buf().lines.line(row);
+                let (from, to) = self.selection_range(row).unwrap_or((0, 0));
+                line[byte_index(&line, from)..byte_index(&line, to)].to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        Some(text)
+    }
+
+    fn delete_selection(&mut self) {
+        if let Some((start, end)) = self.selection_bounds() {
+            self.begin_edit();
+            self.yank_register = self.selected_text().map(|text| (text, YankKind::Char));
+            let end = (end.0, (end.1 + 1).min(self.buf().lines.line_len(end.0)));
+            self.buf_mut().lines.remove(start, end);
+            self.buf_mut().cursor = (start.0, start.1.min(self.buf().lines.line_len(start.0)));
+            self.scroll_to_cursor();
+        }
+        self.exit_visual_mode();
+    }
+
+    fn yank_selection(&mut self) {
+        if let Some((start, _)) = self.selection_bounds() {
+            if let Some(text) = self.selected_text() {
+                self.yank(text, YankKind::Char);
+            }
+            self.buf_mut().cursor = start;
+        }
+        self.exit_visual_mode();
+    }
+
+    fn toggle_selection_case(&mut self) {
+        if let Some((start, end)) = self.selection_bounds() {
+            self.begin_edit();
+            for row in start.0..=end.0 {
+                if let Some((from, to)) = self.selection_range(row) {
+                    self.toggle_case_range(row, from, to);
+                }
+            }
+            self.buf_mut().cursor = start;
+        }
+        self.exit_visual_mode();
+    }
+
+    fn toggle_case_range(&mut self, row: usize, from: usize, to: usize) {
+        let line: String = self
+            .buf()
+            .lines
+            .line(row)
+            .chars()
+            .enumerate()
+            .map(|(col, c)| if (from..to).contains(&col) { toggle_case(c) } else { c })
+            .collect();
+        self.buf_mut().lines.set_line(row, &line);
+    }
+
+    fn yank_lines(&mut self, first: usize, last: usize) {
+        let text = (first..=last).map(|row| self.buf().lines.line(row)).collect::<Vec<_>>().join("\n");
+        self.yank(text, YankKind::Line);
+    }
+
+    fn yank(&mut self, text: String, kind: YankKind) {
+        match kind {
+            YankKind::Line => self.clipboard.set(&format!("{}\n", text)),
+            YankKind::Char => self.clipboard.set(&text),
+        }
+        self.yank_register = Some((text, kind));
+    }
+
+    fn clipboard_register(&mut self) -> Option<(String, YankKind)> {
+        let text = self.clipboard.get().filter(|text| !text.is_empty())?;
+        Some(match text.strip_suffix('\n') {
+            Some(lines) => (lines.to_string(), YankKind::Line),
+            None => (text, YankKind::Char),
+        })
+    }
+
+    fn paste(&mut self, after: bool, count: usize) {
+        let Some((text, kind)) = self.yank_register.clone().or_else(|| self.clipboard_register()) else {
+            self.fail("Nothing to paste".to_string());
+            return;
+        };
+        self.begin_edit();
+        match kind {
+            YankKind::Line => {
+                let row = if after { self.buf().cursor.0 + 1 } else { self.buf().cursor.0 };
+                let text = vec![text; count].join("\n");
+                for (i, line) in text.split('\n').enumerate() {
+                    self.buf_mut().lines.insert_line(row + i, line);
+                }
+                self.buf_mut().cursor = (row, first_non_blank(&self.buf().lines.line(row)));
+            }
+            YankKind::Char => {
+                let len = self.buf().lines.line_len(self.buf().cursor.0);
+                let col = if after { (self.buf().cursor.1 + 1).min(len) } else { self.buf().cursor.1 };
+                let end = self.insert_text((self.buf().cursor.0, col), &text.repeat(count));
+                self.buf_mut().cursor = (end.0, end.1.saturating_sub(1));
+            }
+        }
+        self.scroll_to_cursor();
+    }
+
+    fn insert_text(&mut self, (row, col): (usize, usize), text: &str) -> (usize, usize) {
+        self.buf_mut().lines.insert((row, col), text);
+        match text.rsplit_once('\n') {
+            Some((_, last)) => (row + text.matches('\n').count(), char_len(last)),
+            None => (row, col + char_len(text)),
+        }
+    }
+
+    fn delete_lines(&mut self, first: usize, last: usize) {
+        self.begin_edit();
+        self.yank_lines(first, last);
+        self.buf_mut().lines.remove_lines(first, last);
+        let row = first.min(self.buf().lines.len() - 1);
+        self.buf_mut().cursor = (row, first_non_blank(&self.buf().lines.line(row)));
+        self.scroll_to_cursor();
+    }
+
+    fn begin_insert_edit(&mut self) {
+        let buf = self.buf_mut();
+        buf.undo_history.record_grouped(&buf.lines, buf.cursor);
+        self.mark_modified();
+    }
+
+    fn open_line(&mut self, below: bool) {
+        self.begin_insert_edit();
+        let indent = if self.auto_indent {
+            leading_whitespace(&self.buf().lines.line(self.buf().cursor.0)).to_string()
+        } else {
+            String::new()
+        };
+        let row = if below { self.buf().cursor.0 + 1 } else { self.buf().cursor.0 };
+        self.buf_mut().cursor = (row, char_len(&indent));
+        self.buf_mut().lines.insert_line(row, &indent);
+        self.mode = Mode::Insert;
+    }
+
+    fn cut_range(&mut self, row: usize, from: usize, to: usize) -> String {
+        let len = self.buf().lines.line_len(row);
+        self.buf_mut().lines.remove((row, from.min(len)), (row, to.min(len)))
+    }
+
+    fn change_word(&mut self) {
+        let (row, col) = self.buf().cursor;
+        let len = self.buf().lines.line_len(row);
+        if col >= len {
+            self.mode = Mode::Insert;
+            return;
+        }
+        let class = char_class(self.char_at(self.buf().cursor));
+        let mut end = col;
+        while end < len && char_class(self.char_at((row, end))) == class {
+            end += 1;
+        }
+        self.begin_insert_edit();
+        let removed = self.cut_range(row, col, end);
+        self.yank_register = Some((removed, YankKind::Char));
+        self.mode = Mode::Insert;
+    }
+
+    fn change_line(&mut self, count: usize) {
+        self.begin_insert_edit();
+        let row = self.buf().cursor.0;
+        let last = (row + count - 1).min(self.buf().lines.len() - 1);
+        let removed: Vec<String> = (row..=last).map(|row| self.buf().lines.line(row)).collect();
+        let indent = if self.auto_indent { leading_whitespace(&removed[0]).to_string() } else { String::new() };
+        self.buf_mut().cursor.1 = char_len(&indent);
+        self.buf_mut().lines.splice(row, last, &[indent]);
+        let text = removed.join("\n");
+        self.yank_register = Some((text, YankKind::Line));
+        self.mode = Mode::Insert;
+    }
+
+    fn change_to_line_end(&mut self) {
+        let (row, col) = self.buf().cursor;
+        let len = self.buf().lines.line_len(row);
+        if col < len {
+            self.begin_insert_edit();
+            let removed = self.cut_range(row, col, len);
+            self.yank_register = Some((removed, YankKind::Char));
+        }
+        self.mode = Mode::Insert;
+    }
+
+    fn replace_char(&mut self, c: char) {
+        self.begin_edit();
+        let (row, col) = self.buf().cursor;
+        let removed = self.cut_range(row, col, col + 1);
+        if !removed.is_empty() {
+            self.yank_register = Some((removed, YankKind::Char));
+        }
+        self.buf_mut().lines.insert_char((row, col), c);
+        if c == '\n' {
+            self.buf_mut().cursor = (row + 1, 0);
+        }
+    }
+
+    fn delete_to_line_end(&mut self) {
+        let (row, col) = self.buf().cursor;
+        if col >= self.buf().lines.line_len(row) {
+            return;
+        }
+        self.begin_edit();
+        let len = self.buf().lines.line_len(row);
+        let removed = self.buf_mut().lines.remove((row, col), (row, len));
+        self.buf_mut().cursor.1 = col.saturating_sub(1);
+        self.yank_register = Some((removed, YankKind::Char));
+    }
+
+    fn delete_chars(&mut self, count: usize) {
+        if self.buf().cursor.1 >= self.buf().lines.line_len(self.buf().cursor.0) {
+            return;
+        }
+        self.begin_edit();
+        let removed = self.cut_range(self.buf().cursor.0, self.buf().cursor.1, self.buf().cursor.1 + count);
+        let buf = self.buf_mut();
+        buf.cursor.1 = buf.cursor.1.min(buf.lines.line_len(buf.cursor.0).saturating_sub(1));
+        self.yank_register = Some((removed, YankKind::Char));
+    }
+
+    fn toggle_case(&mut self, count: usize) {
+        let (row, col) = self.buf().cursor;
+        let len = self.buf().lines.line_len(row);
+        if col >= len {
+            return;
+        }
+        self.begin_edit();
+        self.toggle_case_range(row, col, col + count);
+        self.buf_mut().cursor.1 = (col + count).min(len - 1);
+    }
+
+    fn add_to_number(&mut self, amount: i64) {
+        let (row, col) = self.buf().cursor;
+        let chars: Vec<char> = self.buf().lines.line(row).chars().collect();
+        let Some(mut start) = (col..chars.len()).find(|&i| chars[i].is_ascii_digit()) else {
+            return;
+        };
+        while start > 0 && chars[start - 1].is_ascii_digit() {
+            start -= 1;
+        }
+        let end = (start..chars.len()).find(|&i| !chars[i].is_ascii_digit()).unwrap_or(chars.len());
+        if start > 0 && chars[start - 1] == '-' {
+            start -= 1;
+        }
+        let text: String = chars[start..end].iter().collect();
+        let Some(number) = text.parse::<i64>().ok().and_then(|number| number.checked_add(amount)) else {
+            self.fail("Number out of range".to_string());
+            return;
+        };
+        self.begin_edit();
+        let number = number.to_string();
+        let lines = &mut self.buf_mut().lines;
+        lines.remove((row, start), (row, end));
+        lines.insert((row, start), &number);
+        self.buf_mut().cursor.1 = start + char_len(&number) - 1;
+    }
+
+    fn join_lines(&mut self, count: usize) {
+        let row = self.buf().cursor.0;
+        if row + 1 >= self.buf().lines.len() {
+            return;
+        }
+        self.begin_edit();
+        for _ in 0..count.min(self.buf().lines.len() - 1 - row) {
+            let buf = self.buf_mut();
+            let next = buf.lines.remove_line(row + 1);
+            let next = next.trim_start();
+            let line = buf.lines.line(row);
+            buf.cursor.1 = char_len(&line);
+            let separator = if !next.is_empty() && !line.is_empty() && !line.ends_with(char::is_whitespace) { " " } else { "" };
+            buf.lines.insert((row, buf.cursor.1), &(separator.to_string() + next));
+        }
+    }
+
+    fn delete_line(&mut self, count: usize) {
+        let last = (self.buf().cursor.0 + count - 1).min(self.buf().lines.len() - 1);
+        self.delete_lines(self.buf().cursor.0, last);
+    }
+
+    fn try_quit(&mut self) -> bool {
+        if self.buf().modified {
+            self.fail("Unsaved changes; use :q! to force".to_string());
+            return false;
+        }
+        if let Some(buffer) = self.buffers.iter().find(|buffer| buffer.modified) {
+            self.fail(format!("Unsaved changes in \"{}\"; use :q! to force", buffer.file_path));
+            return false;
+        }
+        true
+    }
+
+    fn undo(&mut self) {
+        let buf = self.buf_mut();
+        match buf.undo_history.undo(&buf.lines, buf.cursor) {
+            Some(snapshot) => self.restore(snapshot),
+            None => self.fail("Already at oldest change".to_string()),
+        }
+    }
+
+    fn redo(&mut self) {
+        let buf = self.buf_mut();
+        match buf.undo_history.redo(&buf.lines, buf.cursor) {
+            Some(snapshot) => self.restore(snapshot),
+            None => self.fail("Already at newest change".to_string()),
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.buf_mut().lines = snapshot.lines;
+        self.buf_mut().cursor = snapshot.cursor;
+        self.scroll_to_cursor();
+        self.status_message.clear();
+    }
+
+    fn page_height(&self) -> usize {
+        self.visible_rows().saturating_sub(1).max(1)
+    }
+
+    // The number of text rows drawn in the active pane.
+    fn visible_rows(&self) -> usize {
+        let (area, _) = self.pane_areas();
+        area.rows.max(1)
+    }
+
+    fn handle_mouse(&mut self, event: MouseEvent) {
+        if matches!(self.mode, Mode::Command | Mode::Search(_)) {
+            return;
+        }
+        match event {
+            MouseEvent::Press(MouseButton::Left, x, y) => {
+                if let (_, Some(area)) = self.pane_areas() {
+                    let (row, col) = ((y as usize).saturating_sub(1), (x as usize).saturating_sub(1));
+                    if (area.top..area.top + area.rows).contains(&row) && (area.left..area.left + area.cols).contains(&col) {
+                        self.switch_pane();
+                    }
+                }
+                if let Some(position) = self.screen_to_position(x, y) {
+                    self.buf_mut().cursor = position;
+                }
+            }
+            MouseEvent::Press(MouseButton::WheelUp, _, _) => self.scroll_screen(WHEEL_SCROLL_LINES, false),
+            MouseEvent::Press(MouseButton::WheelDown, _, _) => self.scroll_screen(WHEEL_SCROLL_LINES, true),
+            _ => {}
+        }
+    }
+
+    fn screen_to_position(&self, x: u16, y: u16) -> Option<(usize, usize)> {
+        let (area, _) = self.pane_areas();
+        let screen_row = (y as usize).checked_sub(area.top + 1)?;
+        let screen_col = (x as usize).checked_sub(area.left + 1)?;
+        if screen_row >= area.rows || screen_col >= area.cols {
+            return None;
+        }
+        let row = (self.buf().scroll_offset + screen_row).min(self.buf().lines.len() - 1);
+        let line = self.buf().lines.line(row);
+        let vcol = screen_col.saturating_sub(self.gutter_width(&self.buf().lines)) + self.buf().h_scroll_offset;
+        let last = match self.mode {
+            Mode::Insert => char_len(&line),
+            _ => char_len(&line).saturating_sub(1),
+        };
+        Some((row, col_at_visual(&line, vcol, self.tab_width).min(last)))
+    }
+
+    fn scroll_screen(&mut self, amount: usize, down: bool) {
+        let last = self.buf().lines.len() - 1;
+        if down {
+            self.buf_mut().scroll_offset = (self.buf().scroll_offset + amount).min(last);
+            let buf = self.buf_mut();
+            buf.cursor.0 = (buf.cursor.0 + amount).min(last);
+        } else {
+            self.buf_mut().scroll_offset = self.buf().scroll_offset.saturating_sub(amount);
+            let buf = self.buf_mut();
+            buf.cursor.0 = buf.cursor.0.saturating_sub(amount);
+        }
+        let buf = self.buf_mut();
+        buf.cursor.1 = buf.cursor.1.min(buf.lines.line_len(buf.cursor.0));
+        self.scroll_to_cursor();
+    }
+
+    // Never leave blank rows below the last line.
+    fn set_scroll_offset(&mut self, offset: usize) {
+        self.buf_mut().scroll_offset = offset.min(self.buf().lines.len().saturating_sub(self.visible_rows()));
+    }
+
+    fn scroll_to_cursor(&mut self) {
+        let page = self.page_height();
+        let margin = self.scroll_margin.min(page.saturating_sub(1) / 2);
+        let top = self.buf().cursor.0.saturating_sub(margin);
+        let bottom = (self.buf().cursor.0 + margin).min(self.buf().lines.len() - 1);
+        if top < self.buf().scroll_offset {
+            self.buf_mut().scroll_offset = top;
+        }
+        if bottom >= self.buf().scroll_offset + page {
+            self.buf_mut().scroll_offset = bottom.saturating_sub(page);
+        }
+
+        let (area, _) = self.pane_areas();
+        let content_width = area.cols.saturating_sub(self.gutter_width(&self.buf().lines)).max(1);
+        let cursor_col = visual_col(&self.buf().lines.line(self.buf().cursor.0), self.buf().cursor.1, self.tab_width);
+        if cursor_col < self.buf().h_scroll_offset {
+            self.buf_mut().h_scroll_offset = cursor_col;
+        } else if cursor_col >= self.buf().h_scroll_offset + content_width {
+            self.buf_mut().h_scroll_offset = cursor_col + 1 - content_width;
+        }
+    }
+
+    fn begin_edit(&mut self) {
+        let buf = self.buf_mut();
+        buf.undo_history.record(&buf.lines, buf.cursor);
+        self.mark_modified();
+    }
+
+    fn mark_modified(&mut self) {
+        self.buf_mut().modified = true;
+        self.recorder.changed = true;
+    }
+
+    fn insert_char(&mut self, c: char) {
+        let buf = self.buf_mut();
+        buf.undo_history.record_grouped(&buf.lines, buf.cursor);
+        self.mark_modified();
+        let buf = self.buf_mut();
+        buf.lines.insert_char(buf.cursor, c);
+        buf.cursor.1 += 1;
+    }
+
+    fn insert_tab(&mut self) {
+        if self.expand_tab {
+            let vcol = visual_col(&self.buf().lines.line(self.buf().cursor.0), self.buf().cursor.1, self.tab_width);
+            for _ in 0..self.tab_width - vcol % self.tab_width {
+                self.insert_char(' ');
+            }
+        } else {
+            self.insert_char('\t');
+        }
+    }
+
+    fn indent_string(&self, width: usize) -> String {
+        if self.expand_tab {
+            " ".repeat(width)
+        } else {
+            "\t".repeat(width / self.tab_width) + &" ".repeat(width % self.tab_width)
+        }
+    }
+
+    fn shift_lines(&mut self, first: usize, last: usize, right: bool) {
+        self.begin_edit();
+        for row in first..=last {
+            let line = self.buf().lines.line(row);
+            if line.is_empty() {
+                continue;
+            }
+            let indent = leading_whitespace(&line);
+            let width = visual_col(indent, char_len(indent), self.tab_width);
+            let new_width = if right { width + self.tab_width } else { width.saturating_sub(self.tab_width) };
+            let new_indent = self.indent_string(new_width);
+            if row == self.buf().cursor.0 {
+                let old_len = char_len(indent);
+                let col = self.buf().cursor.1.max(old_len) - old_len;
+                self.buf_mut().cursor.1 = col + char_len(&new_indent);
+            }
+            self.buf_mut().lines.set_line(row, &(new_indent + &line[indent.len()..]));
+        }
+        let buf = self.buf_mut();
+        buf.cursor.1 = buf.cursor.1.min(buf.lines.line_len(buf.cursor.0));
+    }
+
+    fn insert_newline(&mut self) {
+        self.begin_edit();
+        let auto_indent = self.auto_indent;
+        let buf = self.buf_mut();
+        let indent = if auto_indent { leading_whitespace(&buf.lines.line(buf.cursor.0)).to_string() } else { String::new() };
+        buf.lines.insert(buf.cursor, &format!("\n{}", indent));
+        buf.cursor = (buf.cursor.0 + 1, char_len(&indent));
+    }
+
+    fn delete_char(&mut self) {
+        if self.buf().cursor.1 > 0 || self.buf().cursor.0 > 0 {
+            self.begin_edit();
+        }
+        let buf = self.buf_mut();
+        let (row, col) = buf.cursor;
+        if col > 0 {
+            buf.lines.remove((row, col - 1), (row, col));
+            buf.cursor.1 -= 1;
+        } else if row > 0 {
+            buf.cursor = (row - 1, buf.lines.line_len(row - 1));
+            buf.lines.remove(buf.cursor, (row, 0));
+        }
+    }
+
+    // Writes the buffer to its file, reporting the outcome in the status bar.
+    fn save(&mut self) -> bool {
+        let file_path = self.buf().file_path.clone();
+        if let Err(err) = self.write_to(&file_path) {
+            self.fail(format!("Cannot write \"{}\": {}", self.buf().file_path, err));
+            return false;
+        }
+        self.buf_mut().modified = false;
+        self.status_message = "File saved".to_string();
+        true
+    }
+
+    fn trim_trailing_whitespace(&mut self) {
+        let rows: Vec<usize> = self
+            .buf()
+            .lines
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.ends_with([' ', '\t']))
+            .map(|(row, _)| row)
+            .collect();
+        if rows.is_empty() {
+            return;
+        }
+        self.begin_edit();
+        let buf = self.buf_mut();
+        for row in rows {
+            let line = buf.lines.line(row);
+            buf.lines.set_line(row, line.trim_end_matches([' ', '\t']));
+        }
+        buf.cursor.1 = buf.cursor.1.min(buf.lines.line_len(buf.cursor.0).saturating_sub(1));
+    }
+
+    fn save_as(&mut self, file_path: &str) {
+        if file_path == self.buf().file_path {
+            self.save();
+            return;
+        }
+        match self.write_to(file_path) {
+            Ok(()) => self.status_message = format!("\"{}\" written", file_path),
+            Err(err) => self.fail(format!("Cannot write \"{}\": {}", file_path, err)),
+        }
+    }
+
+    fn write_to(&mut self, file_path: &str) -> io::Result<()> {
+        if self.trim_trailing {
+            self.trim_trailing_whitespace();
+        }
+        let mut file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(file_path)?;
+
+        let mut content = self.buf().lines.join(self.buf().line_ending.as_str());
+        if self.buf().trailing_newline {
+            content.push_str(self.buf().line_ending.as_str());
+        }
+        file.write_all(content.as_bytes())
+    }
+}
+
+fn main() -> io::Result<()> {
+    let mut args: Vec<String> = std::env::args().collect();
+    let readonly = args.iter().skip(1).any(|arg| arg == "-R");
+    args.retain(|arg| arg != "-R");
+    if args.len() < 2 {
+        println!("Usage: {} [-R] <file_path>...", args[0]);
+        return Ok(());
+    }
+
+    let mut editor = match Editor::new(&args[1]) {
+        Ok(editor) => editor,
+        Err(err) => {
+            eprintln!("rim: cannot open \"{}\": {}", args[1], err);
+            process::exit(1);
+        }
+    };
+    for file_path in &args[2..] {
+        if let Err(err) = editor.add_buffer(file_path) {
+            eprintln!("rim: cannot open \"{}\": {}", file_path, err);
+            process::exit(1);
+        }
+    }
+    if readonly {
+        for buffer in &mut editor.buffers {
+            buffer.readonly = true;
+        }
+    }
+    let (mut config, mut warnings) = Config::load();
+    if let Some(home) = std::env::var_os("HOME") {
+        warnings.extend(config.load_rimrc(&Path::new(&home).join(".rimrc")));
+    }
+    editor.apply_config(config);
+    editor.status_message = warnings.join("; ");
+    editor.run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor(content: &str) -> Editor {
+        let mut editor = Editor::with_buffer(Buffer::from_content("test.txt", content));
+        editor.size = (80, 24);
+        editor
+    }
+
+    fn numbered_lines(count: usize) -> String {
+        (1..=count).map(|i| format!("{}\n", i)).collect()
+    }
+
+    // Feeds keys to the editor as typed: Esc, Backspace and Ctrl-<letter> as
+    // their control characters. Returns whether the editor asked to quit.
+    fn press(editor: &mut Editor, keys: &str) -> bool {
+        let mut quit = false;
+        for c in keys.chars() {
+            let key = match c {
+                '\x1b' => Key::Esc,
+                '\x7f' => Key::Backspace,
+                '\x01'..='\x1a' if c != '\n' && c != '\t' => Key::Ctrl((c as u8 - 1 + b'a') as char),
+                c => Key::Char(c),
+            };
+            quit = editor.handle_key(key).unwrap();
+        }
+        quit
+    }
+
+    fn text(editor: &Editor) -> String {
+        editor.buf().lines.join("\n")
+    }
+
+    // Draws the editor and returns the text of each screen row, following
+    // cursor moves and dropping colours, and where the cursor was left.
+    fn screen(editor: &Editor) -> (Vec<String>, (usize, usize)) {
+        let mut output = Vec::new();
+        editor.display(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let mut rows = vec![vec![' '; editor.size.0 as usize]; editor.size.1 as usize];
+        let (mut row, mut col) = (0, 0);
+        let mut chars = output.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\x1b' => {
+                    chars.next();
+                    let mut sequence = String::new();
+                    for c in chars.by_ref() {
+                        sequence.push(c);
+                        if c.is_ascii_alphabetic() {
+                            break;
+                        }
+                    }
+                    if let Some((y, x)) = sequence.strip_suffix('H').and_then(|position| position.split_once(';')) {
+                        (row, col) = (y.parse::<usize>().unwrap() - 1, x.parse::<usize>().unwrap() - 1);
+                    }
+                }
+                '\n' | '\r' => {}
+                c => {
+                    if let Some(cell) = rows.get_mut(row).and_then(|cells| cells.get_mut(col)) {
+                        *cell = c;
+                    }
+                    col += 1;
+                }
+            }
+        }
+        let rows = rows.iter().map(|cells| cells.iter().collect::<String>().trim_end().to_string()).collect();
+        (rows, (row, col))
+    }
+
+    fn temp_file(name: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rim-test-{}-{}", process::id(), name));
+        fs::write(&path, content).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn shrinking_the_terminal_keeps_the_cursor_on_screen() {
+        let mut editor = editor(&numbered_lines(100));
+        editor.size = (80, 40);
+        editor.move_to((30, 0));
+        assert_eq!(editor.buf().scroll_offset, 0);
+
+        editor.resize((80, 10));
+        let (area, _) = editor.pane_areas();
+        assert!(editor.buf().scroll_offset <= 30 && 30 < editor.buf().scroll_offset + area.rows);
+
+        editor.resize((80, 40));
+        assert!(editor.buf().scroll_offset <= 30 && 30 < editor.buf().scroll_offset + editor.pane_areas().0.rows);
+    }
+
+    #[test]
+    fn add_buffer_appends_without_switching() {
+        let mut editor = editor("one\n");
+        let path = temp_file("add-buffer", "two\nthree\n");
+        editor.add_buffer(&path).unwrap();
+        assert_eq!(editor.buffers.len(), 2);
+        assert_eq!(editor.current_buffer, 0);
+        assert_eq!(editor.buf().lines.line(0), "one");
+        assert_eq!(editor.buffers[1].lines.len(), 2);
+        assert!(editor.add_buffer(&std::env::temp_dir().to_string_lossy()).is_err());
+        assert_eq!(editor.buffers.len(), 2);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn switching_buffers_keeps_each_cursor() {
+        let mut editor = editor("one\ntwo\nthree\n");
+        let path = temp_file("switch-buffers", "alpha\nbeta\n");
+        editor.add_buffer(&path).unwrap();
+        editor.move_to((2, 1));
+
+        editor.next_buffer(true);
+        assert_eq!(editor.current_buffer, 1);
+        assert_eq!(editor.buf().lines.line(0), "alpha");
+        assert_eq!(editor.buf().cursor, (0, 0));
+        editor.move_to((1, 2));
+
+        editor.next_buffer(true);
+        assert_eq!(editor.current_buffer, 0);
+        assert_eq!(editor.buf().cursor, (2, 1));
+
+        editor.next_buffer(false);
+        assert_eq!(editor.current_buffer, 1);
+        assert_eq!(editor.buf().cursor, (1, 2));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn readonly_buffers_refuse_edits() {
+        let mut editor = editor("one two\nthree\n");
+        press(&mut editor, "x");
+        assert_eq!(text(&editor), "ne two\nthree");
+        editor.buf_mut().readonly = true;
+
+        for keys in ["x", "dd", "iabc\x1b", "u", "\x12", "p", "J", ">>", "Vd\x1b", ":s/one/1/\n", ":%!sort\n"] {
+            editor.status_message.clear();
+            press(&mut editor, keys);
+            assert_eq!(text(&editor), "ne two\nthree", "after {:?}", keys);
+            assert_eq!(editor.status_message, "File is readonly", "after {:?}", keys);
+            assert!(matches!(editor.mode, Mode::Normal));
+        }
+
+        press(&mut editor, ":w\n");
+        assert_eq!(editor.status_message, "File is readonly; use :w! to force");
+        press(&mut editor, ":!true\n");
+        assert_ne!(editor.status_message, "File is readonly");
+    }
+
+    #[test]
+    fn failed_writes_keep_the_editor_open() {
+        let mut editor = editor("one\n");
+        editor.buf_mut().file_path = std::env::temp_dir().join("rim-missing-dir/file.txt").to_string_lossy().into_owned();
+        press(&mut editor, "x");
+        for command in [":w\n", ":w!\n", ":wq\n", ":x\n"] {
+            assert!(!press(&mut editor, command), "{:?} quit", command);
+            assert!(editor.status_message.starts_with("Cannot write"), "after {:?}: {}", command, editor.status_message);
+            assert!(editor.buf().modified);
+        }
+    }
+
+    #[test]
+    fn huge_counts_are_capped() {
+        let mut editor = editor("one\ntwo\nthree\n");
+        press(&mut editor, "99999999999999999999");
+        assert_eq!(editor.pending_count, Some(MAX_COUNT));
+
+        for keys in ["yy", ">>", "<<", "x", "~", "J", "j", "G", "gg", "l", "w", "fe"] {
+            press(&mut editor, &format!("99999999999999999999{}", keys));
+        }
+        press(&mut editor, "u99999999999999999999dd");
+        assert_eq!(text(&editor), "");
+        assert_eq!(editor.buf().cursor, (0, 0));
+    }
+
+    #[test]
+    fn rimrc_is_applied_on_top_of_config() {
+        let content = "# rim settings\ntab_width = 4\nexpandtab = true\n\nnumber=false  # no gutter\n  relativenumber = true\n";
+        let expected = Config { tab_width: 4, expand_tab: true, show_line_numbers: false, relative_line_numbers: true, ..Config::default() };
+        assert_eq!(Config::default().parse_rimrc(content), Ok(expected));
+        assert_eq!(Config::default().parse_rimrc(""), Ok(Config::default()));
+
+        // Settings the file does not mention keep their config.toml values.
+        let mut config = Config { scroll_margin: 3, auto_indent: false, ..Config::default() };
+        let path = temp_file("rimrc", content);
+        assert_eq!(config.load_rimrc(Path::new(&path)), None);
+        let mut editor = editor("");
+        editor.apply_config(config);
+        assert_eq!((editor.tab_width, editor.expand_tab), (4, true));
+        assert_eq!((editor.show_line_numbers, editor.relative_line_numbers), (false, true));
+        assert_eq!((editor.scroll_margin, editor.auto_indent), (3, false));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn missing_or_malformed_rimrc_leaves_the_config_alone() {
+        let defaults = Config::default();
+        assert_eq!(defaults.parse_rimrc("tab_width = 0"), Err("line 1: invalid value for tab_width".to_string()));
+        assert_eq!(defaults.parse_rimrc("expandtab = yes"), Err("line 1: invalid value for expandtab".to_string()));
+        assert_eq!(defaults.parse_rimrc("\ncolors = dark"), Err("line 2: unknown key colors".to_string()));
+        assert_eq!(defaults.parse_rimrc("number"), Err("line 1: expected key = value".to_string()));
+
+        let mut config = Config { tab_width: 2, ..Config::default() };
+        let missing = std::env::temp_dir().join("rim-missing-dir/.rimrc");
+        assert_eq!(config.load_rimrc(&missing), None);
+        assert_eq!(config, Config { tab_width: 2, ..Config::default() });
+
+        let path = temp_file("bad-rimrc", "tab_width = 4\nnumber = maybe\n");
+        let warning = config.load_rimrc(Path::new(&path));
+        assert_eq!(warning.as_deref(), Some(".rimrc: line 2: invalid value for number; none of it was applied"));
+        assert_eq!(config, Config { tab_width: 2, ..Config::default() });
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn passive_bracket_match_stays_within_the_viewport() {
+        let lines = Rope::from(["fn f() {", "    (a[0] < b)", "}", "x > 1"].map(String::from).to_vec());
+        assert_eq!(find_matching_bracket(&lines, 0, 7, &HIGHLIGHT_PAIRS, 0..3), Some((2, 0)));
+        assert_eq!(find_matching_bracket(&lines, 2, 0, &HIGHLIGHT_PAIRS, 0..3), Some((0, 7)));
+        assert_eq!(find_matching_bracket(&lines, 1, 4, &HIGHLIGHT_PAIRS, 0..3), Some((1, 13)));
+        // The match is off screen.
+        assert_eq!(find_matching_bracket(&lines, 0, 7, &HIGHLIGHT_PAIRS, 0..2), None);
+        assert_eq!(find_matching_bracket(&lines, 2, 0, &HIGHLIGHT_PAIRS, 1..3), None);
+        // `<` and `>` are only paired by `%`.
+        assert_eq!(find_matching_bracket(&lines, 1, 10, &HIGHLIGHT_PAIRS, 0..4), None);
+        assert_eq!(find_matching_bracket(&lines, 1, 10, &BRACKET_PAIRS, 0..4), Some((3, 2)));
+    }
+
+    #[test]
+    fn macros_stop_at_a_failed_motion() {
+        let mut editor = editor("a1\na2\na3\na4\n");
+        press(&mut editor, "qaxjq9@a");
+        assert_eq!(text(&editor), "1\n2\n3\n4");
+        assert!(!editor.playing_macro);
+    }
+
+    #[test]
+    fn macros_stop_when_the_pattern_is_not_found() {
+        let mut editor = editor("foo a\nfoo b\nfoo c\n");
+        press(&mut editor, "qa/foo\nxq5@a");
+        assert_eq!(text(&editor), "oo a\noo b\noo c");
+        assert_eq!(editor.status_message, "Pattern not found");
+    }
+
+    #[test]
+    fn only_successful_commands_enter_the_history() {
+        let mut editor = editor("one\ntwo\nthree\n");
+        for command in [":2\n", ":frobnicate\n", ":s/x/y/\n", ":9,1s/o/0/\n", ":set expandtab\n", ":set bogus\n", ":s/o/0/\n"] {
+            press(&mut editor, command);
+        }
+        assert_eq!(editor.command_history, ["2", "set expandtab", "s/o/0/"]);
+    }
+
+    #[test]
+    fn columns_count_chars_not_bytes() {
+        assert_eq!(byte_index("héllo", 2), 3);
+        assert_eq!(byte_index("日本語", 1), 3);
+        assert_eq!(byte_index("日本語", 3), 9);
+
+        let mut editor = editor("héllo\n日本語\n");
+        press(&mut editor, "l");
+        assert_eq!(editor.buf().cursor, (0, 1));
+        press(&mut editor, "l");
+        assert_eq!(editor.buf().cursor, (0, 2));
+        press(&mut editor, "ix\x1b");
+        assert_eq!(editor.buf().lines.line(0), "héxllo");
+
+        press(&mut editor, "j0ll");
+        assert_eq!(editor.buf().cursor, (1, 2));
+        press(&mut editor, "i\x7f\x1b");
+        assert_eq!(editor.buf().lines.line(1), "日語");
+        assert_eq!(editor.buf().cursor, (1, 1));
+        press(&mut editor, "Aß\x1b");
+        assert_eq!(editor.buf().lines.line(1), "日語ß");
+    }
+
+    #[test]
+    fn undo_and_redo_a_typed_word() {
+        let mut editor = editor("end\n");
+        press(&mut editor, "ihello \x1b");
+        press(&mut editor, "0iand \x1b");
+        assert_eq!(text(&editor), "and hello end");
+
+        // Each insert is one undo step, not one per character.
+        press(&mut editor, "u");
+        assert_eq!(text(&editor), "hello end");
+        press(&mut editor, "u");
+        assert_eq!(text(&editor), "end");
+        assert_eq!(editor.buf().cursor, (0, 0));
+        press(&mut editor, "u");
+        assert_eq!(editor.status_message, "Already at oldest change");
+
+        press(&mut editor, "\x12");
+        assert_eq!(text(&editor), "hello end");
+        press(&mut editor, "\x12");
+        assert_eq!(text(&editor), "and hello end");
+        press(&mut editor, "\x12");
+        assert_eq!(editor.status_message, "Already at newest change");
+    }
+
+    #[test]
+    fn yank_a_line_and_paste_it_three_lines_down() {
+        let mut editor = editor("one\n  two\nthree\nfour\nfive\n");
+        press(&mut editor, "jyy3jp");
+        assert_eq!(text(&editor), "one\n  two\nthree\nfour\nfive\n  two");
+        assert_eq!(editor.buf().cursor, (5, 2));
+
+        press(&mut editor, "ggP");
+        assert_eq!(editor.buf().lines.line(0), "  two");
+        assert_eq!(editor.buf().cursor, (0, 2));
+    }
+
+    #[test]
+    fn quitting_a_modified_buffer_is_refused() {
+        let mut clean = editor("one\n");
+        assert!(press(&mut clean, ":q\n"));
+
+        let mut editor = editor("one\n");
+        press(&mut editor, "x");
+        assert!(editor.buf().modified);
+        assert!(!press(&mut editor, ":q\n"));
+        assert_eq!(editor.status_message, "Unsaved changes; use :q! to force");
+        assert!(press(&mut editor, ":q!\n"));
+    }
+
+    #[test]
+    fn word_motions_stop_at_punctuation_and_spaces() {
+        let mut editor = editor("foo.bar baz\nqux\n");
+        let mut stops = Vec::new();
+        for _ in 0..4 {
+            press(&mut editor, "w");
+            stops.push(editor.buf().cursor);
+        }
+        assert_eq!(stops, [(0, 3), (0, 4), (0, 8), (1, 0)]);
+
+        stops.clear();
+        for _ in 0..4 {
+            press(&mut editor, "b");
+            stops.push(editor.buf().cursor);
+        }
+        assert_eq!(stops, [(0, 8), (0, 4), (0, 3), (0, 0)]);
+
+        stops.clear();
+        for _ in 0..4 {
+            press(&mut editor, "e");
+            stops.push(editor.buf().cursor);
+        }
+        assert_eq!(stops, [(0, 2), (0, 3), (0, 6), (0, 10)]);
+    }
+
+    #[test]
+    fn caret_skips_leading_spaces_and_zero_does_not() {
+        let mut editor = editor("    indented line\n");
+        press(&mut editor, "$^");
+        assert_eq!(editor.buf().cursor, (0, 4));
+        press(&mut editor, "0");
+        assert_eq!(editor.buf().cursor, (0, 0));
+        press(&mut editor, "^");
+        assert_eq!(editor.buf().cursor, (0, 4));
+    }
+
+    #[test]
+    fn substitute_replaces_the_first_or_every_match_on_the_line() {
+        let mut editor = editor("a-a-a\na-a\n");
+        press(&mut editor, ":s/a/b/\n");
+        assert_eq!(text(&editor), "b-a-a\na-a");
+        assert_eq!(editor.status_message, "1 substitution on 1 line");
+
+        press(&mut editor, "$:s/a/bb/g\n");
+        assert_eq!(text(&editor), "b-bb-bb\na-a");
+        assert_eq!(editor.status_message, "2 substitutions on 1 line");
+        assert_eq!(editor.buf().cursor, (0, 4));
+
+        press(&mut editor, ":s//x/\n");
+        assert_eq!(editor.status_message, "Empty search pattern");
+        assert_eq!(text(&editor), "b-bb-bb\na-a");
+    }
+
+    #[test]
+    fn substitute_over_the_whole_file_or_a_line_range() {
+        let mut file = editor("foo foo\nbar\nfoo\n");
+        press(&mut file, ":%s/foo/bar/g\n");
+        assert_eq!(text(&file), "bar bar\nbar\nbar");
+        assert_eq!(file.status_message, "3 substitutions on 2 lines");
+
+        let mut editor = editor("x\nxx\nx\nxx\nx\n");
+        press(&mut editor, ":2,4s/x/y/\n");
+        assert_eq!(text(&editor), "x\nyx\ny\nyx\nx");
+        assert_eq!(editor.status_message, "3 substitutions on 3 lines");
+    }
+
+    #[test]
+    fn x_deletes_the_char_under_the_cursor() {
+        let mut editor = editor("héllo\n\n");
+        press(&mut editor, "lx");
+        assert_eq!(text(&editor), "hllo\n");
+        assert_eq!(editor.buf().cursor, (0, 1));
+        assert!(matches!(&editor.yank_register, Some((removed, YankKind::Char)) if removed == "é"));
+
+        press(&mut editor, "$x");
+        assert_eq!(text(&editor), "hll\n");
+        assert_eq!(editor.buf().cursor, (0, 2));
+
+        press(&mut editor, "jx");
+        assert_eq!(text(&editor), "hll\n");
+        assert_eq!(editor.buf().cursor, (1, 0));
+    }
+
+    #[test]
+    fn o_and_shift_o_open_an_indented_line_in_insert_mode() {
+        let mut editor = editor("  one\ntwo\n");
+        press(&mut editor, "o");
+        assert_eq!(text(&editor), "  one\n  \ntwo");
+        assert_eq!(editor.buf().cursor, (1, 2));
+        assert!(matches!(editor.mode, Mode::Insert));
+
+        press(&mut editor, "\x1bggO");
+        assert_eq!(text(&editor), "  \n  one\n  \ntwo");
+        assert_eq!(editor.buf().cursor, (0, 2));
+        assert!(matches!(editor.mode, Mode::Insert));
+    }
+
+    #[test]
+    fn a_shift_a_and_shift_i_pick_the_insert_column() {
+        let mut editor = editor("  hello world\n");
+        press(&mut editor, "wla");
+        assert_eq!(editor.buf().cursor, (0, 4));
+        assert!(matches!(editor.mode, Mode::Insert));
+
+        press(&mut editor, "\x1bA");
+        assert_eq!(editor.buf().cursor, (0, 13));
+        assert!(matches!(editor.mode, Mode::Insert));
+
+        press(&mut editor, "\x1bbI");
+        assert_eq!(editor.buf().cursor, (0, 2));
+        assert!(matches!(editor.mode, Mode::Insert));
+    }
+
+    #[test]
+    fn enter_copies_the_indentation_of_the_line() {
+        let mut editor = editor("    let x = 1;\n");
+        press(&mut editor, "A\nlet y\x1b");
+        assert_eq!(text(&editor), "    let x = 1;\n    let y");
+
+        press(&mut editor, "0wi\n");
+        assert_eq!(text(&editor), "    let x = 1;\n    \n    let y");
+        assert_eq!(editor.buf().cursor, (2, 4));
+    }
+
+    #[test]
+    fn counts_repeat_motions_and_deletes() {
+        let mut editor = editor("zero\none\ntwo\nthree\nfour\nfive\nsix\nseven\n");
+        press(&mut editor, "3j");
+        assert_eq!(editor.buf().cursor, (3, 0));
+
+        press(&mut editor, "2x");
+        assert_eq!(editor.buf().lines.line(3), "ree");
+
+        press(&mut editor, "3dd");
+        assert_eq!(text(&editor), "zero\none\ntwo\nsix\nseven");
+        assert_eq!(editor.buf().cursor.0, 3);
+    }
+
+    #[test]
+    fn dot_repeats_the_last_change() {
+        let mut editor = editor("abcdef\n");
+        press(&mut editor, "x.");
+        assert_eq!(text(&editor), "cdef");
+
+        press(&mut editor, "ifoo\x1b$.");
+        assert_eq!(text(&editor), "foocdefoof");
+    }
+
+    #[test]
+    fn crlf_files_are_saved_with_crlf() {
+        let path = temp_file("crlf.txt", "one\r\ntwo\r\n");
+        let mut editor = Editor::with_buffer(Buffer::open(&path).unwrap());
+        editor.size = (80, 24);
+        assert_eq!(text(&editor), "one\ntwo");
+
+        press(&mut editor, "x:w\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "ne\r\ntwo\r\n");
+
+        press(&mut editor, ":set ff=unix\n:w\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "ne\ntwo\n");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn a_final_newline_is_written_only_if_the_file_had_one() {
+        for content in ["one\ntwo\n", "one\ntwo"] {
+            let path = temp_file("eol.txt", content);
+            let mut editor = Editor::with_buffer(Buffer::open(&path).unwrap());
+            assert_eq!(text(&editor), "one\ntwo");
+            editor.write_to(&path).unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), content);
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn relative_numbers_count_from_the_cursor_line() {
+        let mut editor = editor(&numbered_lines(5));
+        press(&mut editor, "2j:set relativenumber\n");
+        let gutters: Vec<String> = screen(&editor).0[..5].iter().map(|row| row.chars().take(6).collect()).collect();
+        assert_eq!(gutters, ["   2 │", "   1 │", "   3 │", "   1 │", "   2 │"]);
+
+        press(&mut editor, ":set norelativenumber\n");
+        let gutters: Vec<String> = screen(&editor).0[..5].iter().map(|row| row.chars().take(6).collect()).collect();
+        assert_eq!(gutters, ["   1 │", "   2 │", "   3 │", "   4 │", "   5 │"]);
+    }
+
+    #[test]
+    fn the_gutter_grows_past_9999_lines() {
+        let small = editor(&numbered_lines(30));
+        assert_eq!(small.gutter_width(&small.buf().lines), 7);
+        assert_eq!(screen(&small).0[0], "   1 │ 1");
+
+        let mut editor = editor(&numbered_lines(12345));
+        assert_eq!(editor.gutter_width(&editor.buf().lines), 8);
+        press(&mut editor, "Gll");
+        let (rows, cursor) = screen(&editor);
+        assert_eq!(rows[10], "12345 │ 12345");
+        assert_eq!(cursor, (10, 10));
+    }
+
+    #[test]
+    fn edit_opens_a_file_or_an_empty_buffer() {
+        let path = temp_file("edit.txt", "first\nsecond\n");
+        let mut editor = editor("old\n");
+        press(&mut editor, "x");
+        press(&mut editor, &format!(":e {}\n", path));
+        assert_eq!(editor.status_message, "Unsaved changes; use :e! to force");
+        assert_eq!(text(&editor), "ld");
+
+        press(&mut editor, &format!(":e! {}\nj", path));
+        assert_eq!(text(&editor), "first\nsecond");
+        assert_eq!(editor.buf().file_path, path);
+        assert_eq!(editor.buf().cursor, (1, 0));
+        assert!(!editor.buf().modified);
+        fs::remove_file(&path).unwrap();
+
+        press(&mut editor, &format!(":e {}\n", path));
+        assert_eq!(text(&editor), "");
+        assert_eq!(editor.buf().cursor, (0, 0));
+        assert!(editor.status_message.ends_with("[New File]"));
+        assert!(!Path::new(&path).exists());
+    }
+
+    #[test]
+    fn write_to_another_path_keeps_the_current_file() {
+        let path = temp_file("save-as.txt", "");
+        let mut editor = editor("one\ntwo\n");
+        press(&mut editor, &format!("x:w {}\n", path));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "ne\ntwo\n");
+        assert_eq!(editor.buf().file_path, "test.txt");
+        assert!(editor.buf().modified);
+        fs::remove_file(&path).unwrap();
+
+        let missing = std::env::temp_dir().join(format!("rim-test-{}-missing", process::id())).join("file.txt");
+        press(&mut editor, &format!(":w {}\n", missing.display()));
+        assert!(editor.status_message.starts_with("Cannot write"));
+        assert!(!missing.exists());
+    }
+
+    #[test]
+    fn shift_j_joins_lines_with_one_space() {
+        let mut editor = editor("one\ntwo\n    three\nlast\n");
+        press(&mut editor, "J");
+        assert_eq!(text(&editor), "one two\n    three\nlast");
+        assert_eq!(editor.buf().cursor, (0, 3));
+
+        press(&mut editor, "J");
+        assert_eq!(text(&editor), "one two three\nlast");
+        assert_eq!(editor.buf().cursor, (0, 7));
+
+        press(&mut editor, "jJ");
+        assert_eq!(text(&editor), "one two three\nlast");
+    }
+
+    #[test]
+    fn shift_d_and_shift_c_cut_to_the_end_of_the_line() {
+        let mut editor = editor("héllo world\nhéllo world\n");
+        press(&mut editor, "llD");
+        assert_eq!(text(&editor), "hé\nhéllo world");
+        assert_eq!(editor.buf().cursor, (0, 1));
+        assert!(matches!(editor.mode, Mode::Normal));
+        assert!(matches!(&editor.yank_register, Some((removed, YankKind::Char)) if removed == "llo world"));
+
+        press(&mut editor, "jwC");
+        assert_eq!(text(&editor), "hé\nhéllo ");
+        assert_eq!(editor.buf().cursor, (1, 6));
+        assert!(matches!(editor.mode, Mode::Insert));
+        assert!(matches!(&editor.yank_register, Some((removed, YankKind::Char)) if removed == "world"));
+    }
+
+    #[test]
+    fn a_line_number_command_jumps_and_clamps() {
+        let mut editor = editor(&numbered_lines(100));
+        press(&mut editor, "50G$:1\n");
+        assert_eq!(editor.buf().cursor, (0, 0));
+
+        press(&mut editor, "$:9999\n");
+        assert_eq!(editor.buf().cursor, (99, 0));
+        assert!(editor.buf().scroll_offset <= 99 && 99 < editor.buf().scroll_offset + editor.page_height());
+
+        press(&mut editor, "$:42\n");
+        assert_eq!(editor.buf().cursor, (41, 0));
+        assert!(editor.buf().scroll_offset <= 41 && 41 < editor.buf().scroll_offset + editor.page_height());
+    }
+
+    #[test]
+    fn visual_yank_copies_the_selected_text() {
+        let mut editor = editor("the quick brown fox\n");
+        press(&mut editor, "wvey");
+        assert!(matches!(editor.mode, Mode::Normal));
+        assert!(matches!(&editor.yank_register, Some((yanked, YankKind::Char)) if yanked == "quick"));
+        assert_eq!(editor.buf().cursor, (0, 4));
+        assert_eq!(text(&editor), "the quick brown fox");
+
+        press(&mut editor, "$vhhd");
+        assert_eq!(text(&editor), "the quick brown ");
+    }
+
+    #[test]
+    fn visual_line_deletes_whole_lines() {
+        let mut editor = editor("one\ntwo\nthree\nfour\nfive\n");
+        press(&mut editor, "jlVjjd");
+        assert_eq!(text(&editor), "one\nfive");
+        assert_eq!(editor.buf().cursor, (1, 0));
+        assert!(matches!(editor.mode, Mode::Normal));
+        assert!(matches!(&editor.yank_register, Some((yanked, YankKind::Line)) if yanked == "two\nthree\nfour"));
+    }
+
+    #[test]
+    fn clicks_map_to_buffer_positions_past_the_gutter_and_scroll() {
+        let content: String = (0..50).map(|i| format!("line {}\n", i)).collect();
+        let mut editor = editor(&content);
+        editor.set_scroll_offset(10);
+        assert_eq!(editor.gutter_width(&editor.buf().lines), 7);
+        assert_eq!(editor.screen_to_position(11, 3), Some((12, 3)));
+        assert_eq!(editor.screen_to_position(2, 1), Some((10, 0)));
+        assert_eq!(editor.screen_to_position(70, 1), Some((10, 6)));
+        assert_eq!(editor.screen_to_position(11, 23), None);
+
+        press(&mut editor, ":set nonumber\n");
+        assert_eq!(editor.screen_to_position(4, 3), Some((12, 3)));
+    }
+
+    #[test]
+    fn find_char_motions_over_dots() {
+        let mut editor = editor("a.b.c\n");
+        press(&mut editor, "f.");
+        assert_eq!(editor.buf().cursor, (0, 1));
+        press(&mut editor, "t.");
+        assert_eq!(editor.buf().cursor, (0, 2));
+        press(&mut editor, "$F.");
+        assert_eq!(editor.buf().cursor, (0, 3));
+        press(&mut editor, "T.");
+        assert_eq!(editor.buf().cursor, (0, 2));
+        press(&mut editor, "0fz");
+        assert_eq!(editor.buf().cursor, (0, 0));
+    }
+
+    #[test]
+    fn semicolon_repeats_the_last_find() {
+        let mut editor = editor("axbxcxd\n");
+        press(&mut editor, "fx");
+        assert_eq!(editor.buf().cursor, (0, 1));
+        press(&mut editor, ";");
+        assert_eq!(editor.buf().cursor, (0, 3));
+        press(&mut editor, ";");
+        assert_eq!(editor.buf().cursor, (0, 5));
+        press(&mut editor, ",");
+        assert_eq!(editor.buf().cursor, (0, 3));
+
+        // Repeating a `t` skips the match the cursor is already next to.
+        press(&mut editor, "0tx");
+        assert_eq!(editor.buf().cursor, (0, 0));
+        press(&mut editor, ";");
+        assert_eq!(editor.buf().cursor, (0, 2));
+        press(&mut editor, ";");
+        assert_eq!(editor.buf().cursor, (0, 4));
+    }
+
+    #[test]
+    fn percent_jumps_between_matching_brackets() {
+        let mut nested = editor("((()))\n");
+        press(&mut nested, "%");
+        assert_eq!(nested.buf().cursor, (0, 5));
+        press(&mut nested, "%");
+        assert_eq!(nested.buf().cursor, (0, 0));
+        press(&mut nested, "l%");
+        assert_eq!(nested.buf().cursor, (0, 4));
+
+        let mut block = editor("fn main() {\n    if x {\n    }\n}\n");
+        press(&mut block, "$%");
+        assert_eq!(block.buf().cursor, (3, 0));
+        press(&mut block, "%");
+        assert_eq!(block.buf().cursor, (0, 10));
+    }
+
+    #[test]
+    fn page_keys_scroll_by_a_whole_or_half_screen() {
+        let mut editor = editor(&numbered_lines(100));
+        assert_eq!(editor.page_height(), 21);
+        let mut positions = Vec::new();
+        for key in ["\x06", "\x04", "\x15", "\x02", "\x02"] {
+            press(&mut editor, key);
+            positions.push((editor.buf().scroll_offset, editor.buf().cursor.0));
+        }
+        assert_eq!(positions, [(21, 21), (31, 31), (21, 21), (0, 0), (0, 0)]);
+
+        press(&mut editor, "G");
+        let offset = editor.buf().scroll_offset;
+        press(&mut editor, "\x15");
+        assert_eq!((editor.buf().scroll_offset, editor.buf().cursor.0), (offset - 10, 89));
+    }
+
+    #[test]
+    fn z_commands_place_the_cursor_line_in_the_viewport() {
+        let mut editor = editor(&numbered_lines(100));
+        assert_eq!(editor.visible_rows(), 22);
+        let mut offsets = Vec::new();
+        for keys in [":51\nzt", "zz", "zb", ":96\nzt", ":4\nzz", "zb"] {
+            press(&mut editor, keys);
+            offsets.push(editor.buf().scroll_offset);
+        }
+        assert_eq!(offsets, [50, 39, 29, 78, 0, 0]);
+        assert_eq!(editor.buf().cursor, (3, 0));
+
+        // The last line ends up on the last text row, with no blank rows below.
+        press(&mut editor, ":51\nzb");
+        assert_eq!(screen(&editor).0[21], "  51 │ 51");
+        press(&mut editor, ":100\nzt");
+        assert_eq!(screen(&editor).0[21], " 100 │ 100");
+    }
+
+    #[test]
+    fn tab_inserts_spaces_to_the_next_stop_with_expandtab() {
+        let mut editor = editor("\n");
+        press(&mut editor, "iab\t\x1b");
+        assert_eq!(text(&editor), "ab\t");
+        assert_eq!(editor.buf().cursor, (0, 3));
+
+        press(&mut editor, ":set expandtab\nS\tab\t");
+        assert_eq!(text(&editor), format!("{}ab{}", " ".repeat(TAB_WIDTH), " ".repeat(TAB_WIDTH - 2)));
+        assert_eq!(editor.buf().cursor, (0, 2 * TAB_WIDTH));
+
+        press(&mut editor, "\x1b:set noexpandtab\nA\t");
+        assert!(text(&editor).ends_with(" \t"));
+    }
+
+    #[test]
+    fn shifting_lines_indents_and_dedents_by_one_level() {
+        let mut editor = editor("one\n   two\nthree\nfour\nfive\n");
+        press(&mut editor, "l>>");
+        assert_eq!(editor.buf().lines.line(0), "\tone");
+        assert_eq!(editor.buf().cursor, (0, 2));
+
+        press(&mut editor, "j<<");
+        assert_eq!(editor.buf().lines.line(1), "two");
+
+        press(&mut editor, ":set expandtab\nj3>>");
+        assert_eq!(text(&editor), format!("\tone\ntwo\n{0}three\n{0}four\n{0}five", " ".repeat(TAB_WIDTH)));
+    }
+
+    #[test]
+    fn trailing_whitespace_is_trimmed_on_save_only_when_enabled() {
+        let path = temp_file("trim.txt", "keep  \nin  side\t \n");
+        let mut editor = Editor::with_buffer(Buffer::open(&path).unwrap());
+        editor.size = (80, 24);
+        press(&mut editor, "$:w\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "keep  \nin  side\t \n");
+
+        press(&mut editor, ":set trimtrailing\n:w\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "keep\nin  side\n");
+        assert_eq!(editor.buf().cursor, (0, 3));
+        fs::remove_file(path).unwrap();
+
+        // Writing to another path trims the same way.
+        let other = temp_file("trim-other.txt", "");
+        let mut unsaved = Editor::with_buffer(Buffer::from_content("test.txt", "one \ntwo\t\n"));
+        press(&mut unsaved, &format!(":w {}\n", other));
+        assert_eq!(fs::read_to_string(&other).unwrap(), "one \ntwo\t\n");
+        press(&mut unsaved, &format!(":set trimtrailing\n:w {}\n", other));
+        assert_eq!(fs::read_to_string(&other).unwrap(), "one\ntwo\n");
+        fs::remove_file(other).unwrap();
+    }
+
+    #[test]
+    fn directories_and_binary_files_are_refused() {
+        let dir = std::env::temp_dir().join(format!("rim-test-{}-dir", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let err = Buffer::open(&dir.to_string_lossy()).err().unwrap();
+        assert_eq!(err.to_string(), "Is a directory");
+        fs::remove_dir(&dir).unwrap();
+
+        let path = std::env::temp_dir().join(format!("rim-test-{}-binary", process::id()));
+        fs::write(&path, b"ok\n\xff\xfe\n").unwrap();
+        let path = path.to_string_lossy().into_owned();
+        let err = Buffer::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "Binary file, not opened");
+
+        let mut editor = editor("text\n");
+        press(&mut editor, &format!(":e {}\n", path));
+        assert_eq!(editor.status_message, format!("Cannot open \"{}\": Binary file, not opened", path));
+        assert_eq!(text(&editor), "text");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn searches_wrap_around_and_say_so() {
+        let mut editor = editor("foo\nbar\nfoo\n");
+        press(&mut editor, "/foo\n");
+        assert_eq!(editor.buf().cursor, (2, 0));
+        assert_eq!(editor.status_message, "/foo");
+
+        press(&mut editor, "n");
+        assert_eq!(editor.buf().cursor, (0, 0));
+        assert_eq!(editor.status_message, "search hit BOTTOM, continuing at TOP");
+
+        press(&mut editor, "n");
+        assert_eq!(editor.buf().cursor, (2, 0));
+        assert_eq!(editor.status_message, "/foo");
+
+        press(&mut editor, "N");
+        assert_eq!(editor.status_message, "?foo");
+        press(&mut editor, "N");
+        assert_eq!(editor.buf().cursor, (2, 0));
+        assert_eq!(editor.status_message, "search hit TOP, continuing at BOTTOM");
+    }
+
+    #[test]
+    fn failing_shell_commands_are_errors() {
+        let mut editor = editor("one\n");
+        press(&mut editor, ":!echo oops >&2; exit 3\n");
+        assert_eq!(editor.status_message, "shell returned 3: oops");
+        assert!(editor.failed);
+        assert!(editor.command_history.is_empty());
+
+        press(&mut editor, "qa:!false\nxq@a");
+        assert_eq!(text(&editor), "ne");
+    }
+
+    #[test]
+    fn failing_filters_leave_the_buffer_alone() {
+        let mut editor = editor("b\na\n");
+        press(&mut editor, ":%!sort; exit 1\n");
+        assert_eq!(text(&editor), "b\na");
+        assert_eq!(editor.status_message, "shell returned 1, buffer unchanged");
+        assert!(!editor.buf().modified);
+
+        press(&mut editor, "qa:%!false\nxq@a");
+        assert_eq!(text(&editor), "\na");
+    }
+
+    #[test]
+    fn the_jump_list_stays_capped_when_jumping_back() {
+        let mut editor = editor(&numbered_lines(300));
+        for row in 1..=JUMP_LIST_DEPTH + 20 {
+            press(&mut editor, &format!("{}G", row));
+        }
+        assert_eq!(editor.buf().jump_list.len(), JUMP_LIST_DEPTH);
+
+        press(&mut editor, "\x0f");
+        assert_eq!(editor.buf().jump_list.len(), JUMP_LIST_DEPTH);
+        assert_eq!(editor.buf().cursor.0, JUMP_LIST_DEPTH + 18);
+        for _ in 0..10 {
+            press(&mut editor, "\t\x0f");
+        }
+        assert_eq!(editor.buf().jump_list.len(), JUMP_LIST_DEPTH);
+        press(&mut editor, "\t");
+        assert_eq!(editor.buf().cursor.0, JUMP_LIST_DEPTH + 19);
+    }
+}
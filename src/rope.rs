@@ -0,0 +1,309 @@
+use std::ops::Range;
+
+// The text of a buffer, stored as a char-level rope. Lines are separated by a
+// single '\n' (the file's own line ending is restored on save), so a rope
+// always holds at least one, possibly empty, line.
+//
+// Inserting or removing characters and looking up a line are O(log n), and
+// clones share their nodes, so an undo snapshot costs O(1) and an edit only
+// copies the nodes on the path to the changed text.
+#[derive(Clone, Default)]
+pub struct Rope {
+    text: ropey::Rope,
+}
+
+impl Rope {
+    // The number of lines.
+    pub fn len(&self) -> usize {
+        self.text.len_lines()
+    }
+
+    pub fn line(&self, row: usize) -> String {
+        let line = self.text.line(row);
+        let len = self.line_len(row);
+        line.slice(..len).to_string()
+    }
+
+    // The length of a line in chars, excluding the line break.
+    pub fn line_len(&self, row: usize) -> usize {
+        let len = self.text.line(row).len_chars();
+        if row + 1 < self.len() {
+            len - 1
+        } else {
+            len
+        }
+    }
+
+    fn char_index(&self, (row, col): (usize, usize)) -> usize {
+        assert!(col <= self.line_len(row), "column {} out of bounds for line {}", col, row);
+        self.text.line_to_char(row) + col
+    }
+
+    // Inserts text at a position; newlines in it start new lines.
+    pub fn insert(&mut self, position: (usize, usize), text: &str) {
+        self.text.insert(self.char_index(position), text);
+    }
+
+    pub fn insert_char(&mut self, position: (usize, usize), c: char) {
+        self.text.insert_char(self.char_index(position), c);
+    }
+
+    // Removes the text from `start` up to `end`, which may be on a later
+    // line, returning it.
+    pub fn remove(&mut self, start: (usize, usize), end: (usize, usize)) -> String {
+        let range: Range<usize> = self.char_index(start)..self.char_index(end);
+        assert!(range.start <= range.end, "removal range {:?}..{:?} is reversed", start, end);
+        let removed = self.text.slice(range.clone()).to_string();
+        self.text.remove(range);
+        removed
+    }
+
+    pub fn set_line(&mut self, row: usize, line: &str) {
+        self.remove((row, 0), (row, self.line_len(row)));
+        self.insert((row, 0), line);
+    }
+
+    pub fn insert_line(&mut self, row: usize, line: &str) {
+        assert!(row <= self.len(), "line {} out of bounds", row);
+        if row == self.len() {
+            let end = self.text.len_chars();
+            self.text.insert(end, &format!("\n{}", line));
+        } else {
+            self.text.insert(self.text.line_to_char(row), &format!("{}\n", line));
+        }
+    }
+
+    pub fn remove_line(&mut self, row: usize) -> String {
+        self.remove_lines(row, row).pop().unwrap_or_default()
+    }
+
+    // Removes the lines `first..=last` with their line breaks. Removing every
+    // line leaves a single empty one.
+    pub fn remove_lines(&mut self, first: usize, last: usize) -> Vec<String> {
+        assert!(first <= last && last < self.len(), "lines {}..={} out of bounds", first, last);
+        let removed = (first..=last).map(|row| self.line(row)).collect();
+        let start = self.text.line_to_char(first);
+        let range = if last + 1 < self.len() {
+            start..self.text.line_to_char(last + 1)
+        } else if first > 0 {
+            start - 1..self.text.len_chars()
+        } else {
+            0..self.text.len_chars()
+        };
+        self.text.remove(range);
+        removed
+    }
+
+    // Replaces the lines `first..=last` with `lines`.
+    pub fn splice(&mut self, first: usize, last: usize, lines: &[String]) {
+        if lines.is_empty() {
+            self.remove_lines(first, last);
+            return;
+        }
+        assert!(first <= last && last < self.len(), "lines {}..={} out of bounds", first, last);
+        let start = self.text.line_to_char(first);
+        let end = self.text.line_to_char(last) + self.line_len(last);
+        self.text.remove(start..end);
+        self.text.insert(start, &lines.join("\n"));
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = String> + '_ {
+        (0..self.len()).map(|row| self.line(row))
+    }
+
+    pub fn join(&self, separator: &str) -> String {
+        if separator == "\n" {
+            return self.text.to_string();
+        }
+        self.lines().collect::<Vec<_>>().join(separator)
+    }
+}
+
+impl From<Vec<String>> for Rope {
+    fn from(lines: Vec<String>) -> Self {
+        Rope { text: ropey::Rope::from_str(&lines.join("\n")) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A small deterministic generator, so failures are reproducible.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 33) as usize % n.max(1)
+        }
+    }
+
+    fn char_len(line: &str) -> usize {
+        line.chars().count()
+    }
+
+    fn insert_at(line: &mut String, col: usize, text: &str) {
+        let index = line.char_indices().nth(col).map_or(line.len(), |(i, _)| i);
+        line.insert_str(index, text);
+    }
+
+    fn assert_matches(rope: &Rope, model: &[String]) {
+        assert_eq!(rope.len(), model.len());
+        for (row, line) in model.iter().enumerate() {
+            assert_eq!(rope.line(row), *line, "line {}", row);
+            assert_eq!(rope.line_len(row), char_len(line), "length of line {}", row);
+        }
+        assert_eq!(rope.join("\n"), model.join("\n"));
+    }
+
+    fn random_edits(rope: &mut Rope, model: &mut Vec<String>, rng: &mut Lcg, edits: usize) {
+        let words = ["a", "héllo", "日本語", "  ", "x\ty", "0123456789"];
+        for _ in 0..edits {
+            let row = rng.below(model.len());
+            let len = char_len(&model[row]);
+            match rng.below(8) {
+                0 => {
+                    let col = rng.below(len + 1);
+                    let word = words[rng.below(words.len())];
+                    rope.insert((row, col), word);
+                    insert_at(&mut model[row], col, word);
+                }
+                1 => {
+                    let col = rng.below(len + 1);
+                    rope.insert_char((row, col), 'é');
+                    insert_at(&mut model[row], col, "é");
+                }
+                2 => {
+                    let start = rng.below(len + 1);
+                    let end = start + rng.below(len - start + 1);
+                    let expected: String = model[row].chars().skip(start).take(end - start).collect();
+                    assert_eq!(rope.remove((row, start), (row, end)), expected);
+                    model[row] = model[row].chars().take(start).chain(model[row].chars().skip(end)).collect();
+                }
+                3 => {
+                    let at = rng.below(model.len() + 1);
+                    let line = words[rng.below(words.len())].repeat(rng.below(4));
+                    rope.insert_line(at, &line);
+                    model.insert(at, line);
+                }
+                4 => {
+                    let last = (row + rng.below(3)).min(model.len() - 1);
+                    let expected: Vec<String> = model.drain(row..=last).collect();
+                    assert_eq!(rope.remove_lines(row, last), expected);
+                    if model.is_empty() {
+                        model.push(String::new());
+                    }
+                }
+                5 => {
+                    let last = (row + rng.below(3)).min(model.len() - 1);
+                    let lines: Vec<String> = (0..rng.below(4)).map(|i| words[i].to_string()).collect();
+                    rope.splice(row, last, &lines);
+                    model.splice(row..=last, lines);
+                    if model.is_empty() {
+                        model.push(String::new());
+                    }
+                }
+                6 => {
+                    // Text with newlines splits the line it goes into.
+                    let col = rng.below(len + 1);
+                    rope.insert((row, col), "ab\n\ncd");
+                    let tail: String = model[row].chars().skip(col).collect();
+                    model[row] = model[row].chars().take(col).collect::<String>() + "ab";
+                    model.insert(row + 1, String::new());
+                    model.insert(row + 2, "cd".to_string() + &tail);
+                }
+                _ => {
+                    let last = (row + rng.below(3)).min(model.len() - 1);
+                    let start = rng.below(len + 1);
+                    let end = rng.below(char_len(&model[last]) + 1);
+                    let end = if last == row { start.max(end) } else { end };
+                    let head: String = model[row].chars().take(start).collect();
+                    let tail: String = model[last].chars().skip(end).collect();
+                    let mut expected: Vec<String> = model[row..=last].to_vec();
+                    expected[0] = expected[0].chars().skip(start).collect();
+                    let end_in_last = if last == row { end - start } else { end };
+                    expected[last - row] = expected[last - row].chars().take(end_in_last).collect();
+                    assert_eq!(rope.remove((row, start), (last, end)), expected.join("\n"));
+                    model.splice(row..=last, [head + &tail]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn new_rope_has_one_empty_line() {
+        let rope = Rope::default();
+        assert_eq!(rope.len(), 1);
+        assert_eq!(rope.line(0), "");
+        assert_eq!(Rope::from(vec![String::new()]).len(), 1);
+    }
+
+    #[test]
+    fn only_newlines_separate_lines() {
+        let rope = Rope::from(vec!["a\rb".to_string(), "c\u{2028}d".to_string()]);
+        assert_eq!(rope.len(), 2);
+        assert_eq!(rope.line(0), "a\rb");
+        assert_eq!(rope.line_len(1), 3);
+    }
+
+    #[test]
+    fn random_edits_match_a_vec_of_lines() {
+        let mut model: Vec<String> = (0..50).map(|i| format!("line {}", i)).collect();
+        let mut rope = Rope::from(model.clone());
+        let mut rng = Lcg(1);
+        for _ in 0..20 {
+            random_edits(&mut rope, &mut model, &mut rng, 100);
+            assert_matches(&rope, &model);
+        }
+    }
+
+    #[test]
+    fn growing_and_shrinking_splits_and_merges_nodes() {
+        // Enough text to need many levels of nodes, then removed again so they merge.
+        let mut model = vec![String::new()];
+        let mut rope = Rope::default();
+        for row in 0..5000 {
+            let line = format!("{} {}", row, "日本語 héllo ".repeat(row % 7));
+            rope.insert_line(row, &line);
+            model.insert(row, line);
+        }
+        for col in 0..2000 {
+            rope.insert_char((2500, col), 'x');
+            insert_at(&mut model[2500], col, "x");
+        }
+        assert_matches(&rope, &model);
+
+        while model.len() > 3 {
+            let row = model.len() / 3;
+            let last = (row + 9).min(model.len() - 1);
+            assert_eq!(rope.remove_lines(row, last), model.drain(row..=last).collect::<Vec<_>>());
+        }
+        assert_matches(&rope, &model);
+
+        rope.remove_lines(0, rope.len() - 1);
+        assert_matches(&rope, &[String::new()]);
+    }
+
+    #[test]
+    fn clones_are_unaffected_by_later_edits() {
+        let mut model: Vec<String> = (0..3000).map(|i| format!("line {}", i)).collect();
+        let mut rope = Rope::from(model.clone());
+        let mut rng = Lcg(7);
+        let mut snapshots = Vec::new();
+        for _ in 0..10 {
+            snapshots.push((rope.clone(), model.clone()));
+            random_edits(&mut rope, &mut model, &mut rng, 50);
+        }
+        assert_matches(&rope, &model);
+        for (snapshot, expected) in &snapshots {
+            assert_matches(snapshot, expected);
+        }
+
+        // Editing a snapshot leaves the rope it was taken from alone.
+        let (mut snapshot, _) = snapshots.pop().unwrap();
+        snapshot.insert((0, 0), "changed ");
+        snapshot.remove_lines(1, 10);
+        assert_matches(&rope, &model);
+    }
+}